@@ -5,7 +5,10 @@ use micro_http_async::HtmlConstructor;
 use micro_http_async::HttpMethod;
 use micro_http_async::Request;
 use micro_http_async::Response;
-use micro_http_async::Route;
+use micro_http_async::ErrorRoute;
+use micro_http_async::HttpError;
+use micro_http_async::StaticFiles;
+use micro_http_async::StatusCode;
 use micro_http_async::Variable;
 use micro_http_async::Vars;
 use micro_http_async::{HttpServer, JSONResponse};
@@ -23,8 +26,8 @@ use micro_http_async::create_route;
 ///
 /// Then, this handler manipulates the request (for post info, or other info etc)
 ///
-/// after, we return the response as a string. It is then served to the user.
-async fn main_handler(request: Request) -> Result<String, String> {
+/// after, we return the response. It is then served to the user.
+async fn main_handler(request: Request) -> Result<Response, String> {
     println!(
         "{:?} -> {:?} {:?}",
         request.user_addr,
@@ -70,7 +73,7 @@ async fn main_handler(request: Request) -> Result<String, String> {
     if request.get_request.contains_key("name") {
         let name = format!(
             "Hello, {}!",
-            request.get_request.get("name").unwrap().to_string()
+            request.get_request.get("name").unwrap()
         );
         vars.insert("name".to_string(), Variable::String(name));
     } else {
@@ -79,16 +82,16 @@ async fn main_handler(request: Request) -> Result<String, String> {
 
     // Construct the page. We need the response code and page to submit, as well as vars to set. It returns the full page including headers.
     let page =
-        HtmlConstructor::construct_page(Response::from(200), "./templates/index.html", vars).await;
+        HtmlConstructor::construct_page(200, "./templates/index.html", vars).await;
 
     // Return the page as a Result. 
     Ok(page)
 }
 
-/// We have to define a custom error handler, which defines what to do when we have a 404
+/// We can define a custom error handler, which defines what to do when we have a 404
 ///
-/// Not doing this WILL result in an unrecoverable panic.
-async fn error_handler(request: Request) -> Result<String, String> {
+/// Without one, a plain `404 Not Found` page is sent.
+async fn error_handler(request: Request, _error: HttpError) -> Result<Response, String> {
     println!(
         "{:?} -> {:?} {:?}",
         request.user_addr,
@@ -106,7 +109,7 @@ async fn error_handler(request: Request) -> Result<String, String> {
     vars.insert("uri".to_string(), Variable::String(test_string));
 
     let page =
-        HtmlConstructor::construct_page(Response::ClientErr, "./templates/err.html", vars).await;
+        HtmlConstructor::construct_page(StatusCode::NOT_FOUND, "./templates/err.html", vars).await;
 
     Ok(page)
 }
@@ -121,7 +124,7 @@ struct TestResponse {
 
 // Then, when we handle the response, we convert the Struct using serde_json. We use the JSONResponse class to create
 // a response we can send back to the user, using that weird looking return_future method and the box::pins lol.
-async fn json_response_handler(request: Request) -> Result<String, String> {
+async fn json_response_handler(request: Request) -> Result<Response, String> {
     println!(
         "{:?} -> {:?} {:?}",
         request.user_addr,
@@ -135,7 +138,7 @@ async fn json_response_handler(request: Request) -> Result<String, String> {
 
     // This differs from the HTMLConstructor, as we don't take vars as an input. Instead,
     // we use JSON constructor to build a JSON compatible response using the result from the serde_json library.
-    let page = JSONResponse::construct_response(Response::Ok, json.to_string()).await;
+    let page = JSONResponse::construct_response(StatusCode::OK, json.to_string()).await;
     Ok(page)
 }
 
@@ -148,32 +151,39 @@ async fn json_response_handler(request: Request) -> Result<String, String> {
 pub async fn main() {
     let use_tls = true;
 
-    let mut http_server = if use_tls {
-        HttpServer::new_tls("127.0.0.1", "443", "./ssl/cert.pem".into(), "./ssl/server.key".into())
-        .await
-        .expect("Error binding to IP/Port")
+    let builder = HttpServer::builder().read_buffer_size(10_000); // Set to 10kb. This allows us to read larger files, for example.
+
+    let builder = if use_tls {
+        builder.bind("127.0.0.1:443").tls("./ssl/cert.pem", "./ssl/server.key")
     }else{
-        HttpServer::new("127.0.0.1", "80")
-        .await
-        .expect("Error binding to IP/Port")
+        builder.bind("127.0.0.1:80")
     };
 
-
-    http_server.set_read_buffer_size(10_000).await.unwrap(); // Set to 10kb. This allows us to read larger files, for example.
+    // Settings are checked here, so a bad value (or a port which is in use) is reported before we start
+    let mut http_server = builder.build().await.expect("Error building the server");
 
     // must be placed on heap so it can be allocated at runtime (alternative is static)
 
     http_server
         .routes
         .add_route("/".to_string(), create_route!(main_handler))
-        .await; // Use the macro
+        .await
+        .unwrap(); // Use the macro
     http_server
         .routes
-        .add_route("err".to_string(), Route::new(Box::new(error_handler)))
-        .await; // Do it manually
+        .set_error_handler(StatusCode::NOT_FOUND, ErrorRoute::new(Box::new(error_handler)))
+        .await; // Error handlers can also be made with the `create_error_route!` macro
+    // Routes can also be registered for a single method. Other methods get a `405 Method Not Allowed`
+    http_server
+        .routes
+        .get("/json", create_route!(json_response_handler))
+        .await
+        .unwrap(); // Adding a route which conflicts with an existing one returns an error
+
+    // Serve the files in ./static (stylesheets, images and so on) under /static
     http_server
         .routes
-        .add_route("/json".to_string(), create_route!(json_response_handler))
+        .mount(StaticFiles::new("/static", "./static"))
         .await;
 
     http_server.listen().await.unwrap();
@@ -0,0 +1,437 @@
+use crate::server::{load_tls_acceptor, print_error, ConnectionSettings, ErrorCallback, Listener};
+use crate::{HttpServer, ServerError};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpListener, ToSocketAddrs};
+
+/// # Build Error
+///
+/// The reasons an [HttpServerBuilder](struct.HttpServerBuilder.html) can't build a server
+#[derive(Debug)]
+pub enum BuildError {
+    /// No address was given to listen on (see [bind](struct.HttpServerBuilder.html#method.bind))
+    NoAddress,
+    /// [tls](struct.HttpServerBuilder.html#method.tls) was called before any listener was added, so there was
+    /// nothing for it to apply to
+    TlsWithoutListener,
+    /// The address to listen on could not be resolved
+    Address(io::Error),
+    /// None of the addresses could be bound, for example because they are already in use
+    Bind {
+        /// The addresses which were tried
+        addrs: Vec<SocketAddr>,
+        /// Why binding the last of them failed
+        error: io::Error,
+    },
+    /// A listener given to the builder could not be used (see [std_listener](struct.HttpServerBuilder.html#method.std_listener))
+    Listener(io::Error),
+    /// The TLS certificate or key could not be loaded
+    Tls(io::Error),
+    /// A setting has a value the server can't work with
+    InvalidSetting {
+        /// The name of the setting
+        setting: &'static str,
+        /// What is wrong with its value
+        reason: &'static str,
+    },
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::NoAddress => write!(f, "no address to listen on was given"),
+            BuildError::TlsWithoutListener => write!(f, "TLS was set up before any address to listen on was given"),
+            BuildError::Address(error) => write!(f, "could not resolve the address to listen on: {}", error),
+            BuildError::Bind { addrs, error } => {
+                let addrs: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
+                write!(f, "could not listen on {}: {}", addrs.join(", "), error)
+            }
+            BuildError::Listener(error) => write!(f, "could not use the listener: {}", error),
+            BuildError::Tls(error) => write!(f, "could not load the TLS certificate or key: {}", error),
+            BuildError::InvalidSetting { setting, reason } => write!(f, "invalid {}: {}", setting, reason),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::Address(error)
+            | BuildError::Bind { error, .. }
+            | BuildError::Listener(error)
+            | BuildError::Tls(error) => Some(error),
+            BuildError::NoAddress | BuildError::TlsWithoutListener | BuildError::InvalidSetting { .. } => None,
+        }
+    }
+}
+
+impl From<BuildError> for io::Error {
+    fn from(error: BuildError) -> Self {
+        match error {
+            BuildError::Address(error)
+            | BuildError::Bind { error, .. }
+            | BuildError::Listener(error)
+            | BuildError::Tls(error) => error,
+            error => io::Error::new(io::ErrorKind::InvalidInput, error.to_string()),
+        }
+    }
+}
+
+/// # HTTP Server Builder
+///
+/// Configures an [HttpServer](struct.HttpServer.html) in one place. Get one with [HttpServer::builder](struct.HttpServer.html#method.builder).
+///
+/// Nothing is checked until [build](#method.build) is called, which binds the address and returns a
+/// [BuildError](enum.BuildError.html) describing the first problem it finds.
+///
+/// A server can listen on several addresses at once, each with or without TLS, all serving the same routes.
+/// Every call to [bind](#method.bind), [listener](#method.listener) or [std_listener](#method.std_listener) adds
+/// a listener, and [tls](#method.tls) turns on TLS for the one added just before it.
+///
+/// **Example**
+/// ```no_run
+/// # use micro_http_async::HttpServer;
+/// # use std::time::Duration;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let http_server = HttpServer::builder()
+///     .bind("0.0.0.0:8443")
+///     .tls("cert.pem", "key.pem")
+///     .max_body_size(1024 * 1024)
+///     .keep_alive_timeout(Duration::from_secs(15))
+///     .max_connections(10_000)
+///     .logging(false)
+///     .build()
+///     .await
+///     .unwrap();
+/// # drop(http_server);
+/// # }
+/// ```
+///
+/// Serving HTTP and HTTPS, on IPv4 and IPv6:
+/// ```no_run
+/// # use micro_http_async::HttpServer;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let http_server = HttpServer::builder()
+///     .bind("0.0.0.0:80")
+///     .bind("0.0.0.0:443")
+///     .tls("cert.pem", "key.pem")
+///     .bind("[::]:80")
+///     .bind("[::]:443")
+///     .tls("cert.pem", "key.pem")
+///     .build()
+///     .await
+///     .unwrap();
+/// # drop(http_server);
+/// # }
+/// ```
+pub struct HttpServerBuilder {
+    /// What to listen on, in the order they were added
+    listeners: Vec<ListenerConfig>,
+    /// Whether `tls` was called before there was a listener for it to apply to
+    tls_without_listener: bool,
+    /// The settings handed to every connection
+    settings: ConnectionSettings,
+    /// Whether connections are kept open between requests
+    keep_alive: bool,
+    /// The most connections served at once
+    max_connections: Option<usize>,
+    /// Whether the server prints to the console
+    logging: bool,
+    /// Where errors go, if not to the console
+    on_error: Option<ErrorCallback>,
+}
+
+impl HttpServerBuilder {
+    /// # New
+    ///
+    /// Create a builder with the default settings, and no address
+    pub(crate) fn new() -> Self {
+        Self {
+            listeners: Vec::new(),
+            tls_without_listener: false,
+            settings: ConnectionSettings::default(),
+            keep_alive: true,
+            max_connections: None,
+            logging: true,
+            on_error: None,
+        }
+    }
+
+    /// # Bind
+    ///
+    /// Add an address to listen on, such as a `SocketAddr` or `"127.0.0.1:8080"`. It is resolved when the server
+    /// is built, without blocking the runtime. If it resolves to several addresses, each is tried in turn until one
+    /// can be bound.
+    ///
+    /// Binding to port 0 lets the OS pick a free port - [HttpServer::local_addr](struct.HttpServer.html#method.local_addr)
+    /// tells you which one it picked.
+    pub fn bind(self, addr: impl ToSocketAddrs + Send + 'static) -> Self {
+        // Nothing is looked up until the future runs, in `build`
+        let lookup = async move { Ok(lookup_host(addr).await?.collect()) }.boxed();
+        self.add_listener(ListenerSource::Addrs(lookup))
+    }
+
+    /// # Listener
+    ///
+    /// Add a tokio `TcpListener` which is already bound to listen on, instead of binding an address.
+    ///
+    /// **Example**
+    /// ```
+    /// # use micro_http_async::HttpServer;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    /// let addr = listener.local_addr().unwrap();
+    ///
+    /// let http_server = HttpServer::builder().listener(listener).build().await.unwrap();
+    /// assert_eq!(http_server.local_addr().unwrap(), addr);
+    /// # }
+    /// ```
+    pub fn listener(self, listener: TcpListener) -> Self {
+        self.add_listener(ListenerSource::Tokio(listener))
+    }
+
+    /// # Std Listener
+    ///
+    /// Add a standard library `TcpListener` which is already bound to listen on, instead of binding an address.
+    /// It is switched to non-blocking mode, as tokio needs.
+    pub fn std_listener(self, listener: std::net::TcpListener) -> Self {
+        self.add_listener(ListenerSource::Std(listener))
+    }
+
+    /// # TLS
+    ///
+    /// Serve HTTPS on the listener added last, with a certificate and key. The certificate and key files must be in
+    /// PEM and KEY formats respectively. The key must not be encrypted.
+    pub fn tls(mut self, cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        match self.listeners.last_mut() {
+            Some(listener) => listener.tls = Some((cert_path.into(), key_path.into())),
+            None => self.tls_without_listener = true,
+        }
+        self
+    }
+
+    /// Add a listener, without TLS
+    fn add_listener(mut self, source: ListenerSource) -> Self {
+        self.listeners.push(ListenerConfig { source, tls: None });
+        self
+    }
+
+    /// # Read Buffer Size
+    ///
+    /// Set how many bytes are read from a connection at a time. The default value is 8192 bytes.
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.settings.read_buffer_size = size;
+        self
+    }
+
+    /// # Max Header Size
+    ///
+    /// Set the largest request line and headers the server will accept. Requests with larger headers are
+    /// answered with `431 Request Header Fields Too Large`. The default value is 16KB.
+    pub fn max_header_size(mut self, size: usize) -> Self {
+        self.settings.max_header_size = size;
+        self
+    }
+
+    /// # Max Body Size
+    ///
+    /// Set the largest request body the server will accept. Requests with a larger body are answered with
+    /// `413 Payload Too Large`. The default value is 10MB.
+    pub fn max_body_size(mut self, size: usize) -> Self {
+        self.settings.max_body_size = size;
+        self
+    }
+
+    /// # Keep Alive
+    ///
+    /// Set whether connections are kept open to serve more requests. If not, every connection is closed after
+    /// its first response. Enabled by default.
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// # Keep Alive Timeout
+    ///
+    /// Set how long a persistent connection may stay idle between requests before it is closed.
+    /// The default value is 5 seconds.
+    pub fn keep_alive_timeout(mut self, keep_alive_timeout: Duration) -> Self {
+        self.settings.keep_alive_timeout = keep_alive_timeout;
+        self
+    }
+
+    /// # Request Timeout
+    ///
+    /// Set how long a client has to send a whole request, headers and body, once it has started sending it.
+    /// Requests which take longer are answered with `408 Request Timeout`. The default value is 30 seconds.
+    pub fn request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.settings.request_timeout = request_timeout;
+        self
+    }
+
+    /// # Handshake Timeout
+    ///
+    /// Set how long a client has to finish the TLS handshake before its connection is closed. The default value is 10 seconds.
+    pub fn handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.settings.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    /// # Max Requests Per Connection
+    ///
+    /// Set how many requests can be served over a single connection before it is closed. The default value is 100.
+    pub fn max_requests_per_connection(mut self, max_requests: usize) -> Self {
+        self.settings.max_requests_per_connection = max_requests;
+        self
+    }
+
+    /// # Shutdown Timeout
+    ///
+    /// Set how long the server waits for requests which are in flight to finish when it shuts down, before
+    /// dropping their connections. The default value is 30 seconds.
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.settings.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// # Max Connections
+    ///
+    /// Set the most connections the server serves at once. Once it is reached, no more connections are accepted
+    /// until one closes (they wait in the OS's backlog). Unlimited by default.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// # Logging
+    ///
+    /// Set whether the server prints the address it listens on, and the errors it runs into, to the console.
+    /// Enabled by default. An [error_callback](#method.error_callback) gets the errors either way.
+    pub fn logging(mut self, logging: bool) -> Self {
+        self.logging = logging;
+        self
+    }
+
+    /// # Error Callback
+    ///
+    /// Set the function which is called with every error the server runs into
+    /// (see [HttpServer::set_error_callback](struct.HttpServer.html#method.set_error_callback)).
+    pub fn error_callback(mut self, callback: impl Fn(&ServerError) + Send + Sync + 'static) -> Self {
+        self.on_error = Some(Arc::new(callback));
+        self
+    }
+
+    /// # Build
+    ///
+    /// Check the settings, load the TLS certificates and keys, and bind the addresses.
+    pub async fn build(self) -> Result<HttpServer, BuildError> {
+        let mut settings = self.settings;
+        validate("read buffer size", settings.read_buffer_size > 0, "must be greater than zero")?;
+        validate("max header size", settings.max_header_size > 0, "must be greater than zero")?;
+        validate("request timeout", !settings.request_timeout.is_zero(), "must be greater than zero")?;
+        validate("handshake timeout", !settings.handshake_timeout.is_zero(), "must be greater than zero")?;
+        validate("max requests per connection", settings.max_requests_per_connection > 0, "a connection must be able to serve at least one request")?;
+        validate("max connections", self.max_connections != Some(0), "the server must be able to serve at least one connection")?;
+        if self.keep_alive {
+            validate("keep alive timeout", !settings.keep_alive_timeout.is_zero(), "must be greater than zero (use `keep_alive(false)` to disable keep-alive)")?;
+        } else {
+            settings.max_requests_per_connection = 1;
+        }
+
+        if self.tls_without_listener {
+            return Err(BuildError::TlsWithoutListener);
+        }
+        if self.listeners.is_empty() {
+            return Err(BuildError::NoAddress);
+        }
+
+        // Load every certificate before binding anything, so a bad one doesn't leave ports bound
+        let mut tls_acceptors = Vec::with_capacity(self.listeners.len());
+        for config in &self.listeners {
+            tls_acceptors.push(match &config.tls {
+                Some((cert_path, key_path)) => Some(load_tls_acceptor(cert_path, key_path).map_err(BuildError::Tls)?),
+                None => None,
+            });
+        }
+
+        let mut listeners = Vec::with_capacity(self.listeners.len());
+        for (config, tls_acceptor) in self.listeners.into_iter().zip(tls_acceptors) {
+            let listener = config.source.into_listener().await?;
+            if self.logging {
+                if let Ok(addr) = listener.local_addr() {
+                    let scheme = if tls_acceptor.is_some() { "https" } else { "http" };
+                    println!("Listening on {}://{}", scheme, addr);
+                }
+            }
+            listeners.push(Listener { listener, tls_acceptor });
+        }
+
+        let on_error = match self.on_error {
+            Some(on_error) => on_error,
+            None if self.logging => Arc::new(print_error),
+            None => Arc::new(|_: &ServerError| {}),
+        };
+
+        Ok(HttpServer::from_parts(listeners, settings, on_error, self.max_connections).await)
+    }
+}
+
+/// # Listener Config
+///
+/// A listener the server will accept connections on, before it is bound
+struct ListenerConfig {
+    /// What to listen on
+    source: ListenerSource,
+    /// The certificate and key to serve TLS with
+    tls: Option<(PathBuf, PathBuf)>,
+}
+
+/// # Listener Source
+///
+/// Where the server's listener comes from
+enum ListenerSource {
+    /// Resolve an address, then bind the first of the addresses it resolves to which can be bound
+    Addrs(BoxFuture<'static, io::Result<Vec<SocketAddr>>>),
+    /// A tokio listener which is already bound
+    Tokio(TcpListener),
+    /// A standard library listener which is already bound
+    Std(std::net::TcpListener),
+}
+
+impl ListenerSource {
+    /// Bind the address, or convert the listener, so it's ready to accept connections
+    async fn into_listener(self) -> Result<TcpListener, BuildError> {
+        match self {
+            ListenerSource::Addrs(lookup) => {
+                let addrs = lookup.await.map_err(BuildError::Address)?;
+                if addrs.is_empty() {
+                    return Err(BuildError::NoAddress);
+                }
+                TcpListener::bind(&addrs[..]).await.map_err(|error| BuildError::Bind { addrs, error })
+            }
+            ListenerSource::Tokio(listener) => Ok(listener),
+            ListenerSource::Std(listener) => {
+                // tokio listeners must not block, or accepting would block the whole runtime thread
+                listener.set_nonblocking(true).map_err(BuildError::Listener)?;
+                TcpListener::from_std(listener).map_err(BuildError::Listener)
+            }
+        }
+    }
+}
+
+/// Fail with an `InvalidSetting` error, unless `valid`
+fn validate(setting: &'static str, valid: bool, reason: &'static str) -> Result<(), BuildError> {
+    if valid {
+        Ok(())
+    } else {
+        Err(BuildError::InvalidSetting { setting, reason })
+    }
+}
@@ -1,16 +1,84 @@
 use std::error::Error;
 use std::io;
+use std::path::Path;
 use tokio::net::TcpStream;
 use tokio_rustls::server::TlsStream;
-use tokio::io::{split, AsyncWriteExt, AsyncReadExt};
+use tokio::io::{AsyncWriteExt, AsyncReadExt, AsyncSeekExt};
+use crate::headers;
+use crate::{Body, Headers, Response};
 
+/// The size of the blocks files are read from disk in, when they are streamed to the client
+const FILE_BLOCK_SIZE: usize = 64 * 1024;
 
+/// Fields which can't be sent as trailers - they control how the request is framed, routed or authenticated,
+/// or how the body is interpreted, so they must be known before the body. Trailers with these names are dropped.
+const FORBIDDEN_TRAILERS: &[&str] = &[
+    "Authorization",
+    "Cache-Control",
+    "Connection",
+    "Content-Encoding",
+    "Content-Length",
+    "Content-Range",
+    "Content-Type",
+    "Cookie",
+    "Expect",
+    "Host",
+    "Keep-Alive",
+    "Max-Forwards",
+    "Proxy-Authorization",
+    "Range",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
 
+
+
+#[allow(clippy::upper_case_acronyms, clippy::large_enum_variant)]
 pub enum ConnectionType{
     Plain(Option<TcpStream>),
     TLS(Option<TlsStream<TcpStream>>)
 }
 
+/// # Read Error
+///
+/// The reasons reading a request from a `Connection` can fail
+#[derive(Debug)]
+pub enum ReadError {
+    /// The client closed the connection before sending a full request
+    Closed,
+    /// The request headers are larger than the maximum header size
+    HeadersTooLarge,
+    /// The request body is larger than the maximum body size. Holds the request line and headers,
+    /// so the error can still be answered with the request in hand.
+    BodyTooLarge(Vec<u8>),
+    /// The request could not be framed, for example because of an invalid `Content-Length`
+    BadRequest(&'static str),
+    /// Some other I/O error occurred
+    Io(io::Error),
+}
+
+impl std::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Closed => write!(f, "connection closed"),
+            ReadError::HeadersTooLarge => write!(f, "request headers too large"),
+            ReadError::BodyTooLarge(_) => write!(f, "request body too large"),
+            ReadError::BadRequest(reason) => write!(f, "bad request: {}", reason),
+            ReadError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ReadError {}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
 /// # Connection
 ///
 /// This struct is a helpful struct to handle the nitty gritty of
@@ -20,6 +88,13 @@ pub struct Connection{
     connection_type: ConnectionType,
     /// The read buffer size
     read_buffer_size: usize,
+    /// The largest request head (request line and headers) we accept
+    max_header_size: usize,
+    /// The largest request body we accept
+    max_body_size: usize,
+    /// Data we've read from the stream, but which isn't part of a request we've returned yet.
+    /// Clients can send the next request before we've answered the last one, so we keep it around.
+    buffer: Vec<u8>,
 
     /// We need to store the TLS stream so we don't run into copy/clone issues
     tls_stream: Option<TlsStream<TcpStream>>,
@@ -29,7 +104,7 @@ impl Connection {
     /// # New
     ///
     /// Create a new connection handler from a `TcpStream`
-    pub fn new(mut connection_type: ConnectionType, read_buffer_size: usize) -> Self {
+    pub fn new(mut connection_type: ConnectionType, read_buffer_size: usize, max_header_size: usize, max_body_size: usize) -> Self {
         // Check if we need to use TLS
         let tls_stream = match connection_type {
             ConnectionType::TLS(ref mut tls_stream) => {
@@ -41,32 +116,228 @@ impl Connection {
         Connection{
             connection_type,
             read_buffer_size,
+            max_header_size,
+            max_body_size,
+            buffer: Vec::new(),
             tls_stream,
         }
     }
 
     /// # Read To String
     ///
-    /// Read the `TcpStream` to a `String`
-    pub async fn read_to_string(&mut self) -> Result<String, &'static str> {
-        let mut string = unsafe {
-            String::from_utf8_unchecked(
-                self.read_to_vec()
-                    .await
-                    .expect("Error reading vec from stream"),
-            )
-        };
-        //.expect("Error decoding stream to utf-8");
+    /// Read the next request from the `TcpStream` to a `String`. Fails if the request is not valid UTF-8,
+    /// use [read_to_vec](#method.read_to_vec) for requests which may contain binary data.
+    pub async fn read_to_string(&mut self) -> Result<String, ReadError> {
+        let mut string = String::from_utf8(self.read_to_vec().await?)
+            .map_err(|_| ReadError::BadRequest("request is not valid UTF-8"))?;
         trim_newline(&mut string);
 
         Ok(string)
     }
 
+    /// # Wait For Request
+    ///
+    /// Wait until the next request has started to arrive (or already has, if the client sent several at once),
+    /// without reading all of it. Until then, the connection is idle.
+    pub async fn wait_for_request(&mut self) -> Result<(), ReadError> {
+        loop {
+            self.skip_empty_lines();
+            // A lone CR may be the start of an empty line, so wait to see what follows it
+            if !self.buffer.is_empty() && self.buffer != b"\r" {
+                return Ok(());
+            }
+            if self.fill_buffer().await? == 0 {
+                return Err(ReadError::Closed);
+            }
+        }
+    }
+
     /// # Read To Vec
     ///
-    /// Read the `TcpStream` to a `Vec<u8>`. Returns a `Result` as we cannot guarantee a successful read.
-    async fn read_to_vec(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut buffer = Vec::with_capacity(self.read_buffer_size);
+    /// Read the next request from the `TcpStream` to a `Vec<u8>`. Returns a `Result` as we cannot guarantee a successful read.
+    ///
+    /// We read until we have all of the headers, and then read exactly `Content-Length` bytes of body, so large bodies
+    /// which arrive over many reads aren't cut short. Anything the client sent after the body is kept for the next request.
+    pub async fn read_to_vec(&mut self) -> Result<Vec<u8>, ReadError> {
+        // Read until we find the end of the headers
+        let head_len = loop {
+            self.skip_empty_lines();
+            if let Some(position) = find_header_end(&self.buffer) {
+                break position + 4;
+            }
+            if self.buffer.len() > self.max_header_size {
+                return Err(ReadError::HeadersTooLarge);
+            }
+            if self.fill_buffer().await? == 0 {
+                return Err(ReadError::Closed);
+            }
+        };
+        if head_len > self.max_header_size {
+            return Err(ReadError::HeadersTooLarge);
+        }
+
+        let head = String::from_utf8_lossy(&self.buffer[..head_len]).to_string();
+        let mut lines = head.trim_end_matches("\r\n").split("\r\n");
+        let request_line = lines.next().unwrap_or("");
+        // The same parser the `Request` uses, so the request is framed with the headers its handler sees
+        let headers = Headers::try_parse(lines).map_err(ReadError::BadRequest)?;
+
+        if is_chunked(request_line, &headers)? {
+            if self.buffer.len() == head_len && expects_continue(&headers) {
+                self.write_continue().await?;
+            }
+            return self.read_chunked(head, head_len).await;
+        }
+
+        let body_len = content_length(&headers)?;
+        if body_len > self.max_body_size {
+            return Err(ReadError::BodyTooLarge(head.into_bytes()));
+        }
+
+        // Clients which sent `Expect: 100-continue` wait for us before sending the body
+        if self.buffer.len() < head_len + body_len && expects_continue(&headers) {
+            self.write_continue().await?;
+        }
+
+        // Read the rest of the body
+        while self.buffer.len() < head_len + body_len {
+            if self.fill_buffer().await? == 0 {
+                return Err(ReadError::Closed);
+            }
+        }
+
+        let buffer: Vec<u8> = self.buffer.drain(..head_len + body_len).collect();
+
+        Ok(buffer) // return our vector
+    }
+
+    /// # Skip Empty Lines
+    ///
+    /// Remove empty lines from the start of the buffer. Some clients send a CRLF after a request body,
+    /// which isn't part of the next request.
+    fn skip_empty_lines(&mut self) {
+        let empty_lines = self.buffer.chunks(2).take_while(|line| *line == b"\r\n").count();
+        self.buffer.drain(..empty_lines * 2);
+    }
+
+    /// # Read Chunked
+    ///
+    /// Decode a body sent with `Transfer-Encoding: chunked`, which starts at `body_start` in our buffer.
+    ///
+    /// Handlers shouldn't have to care how the body was sent, so the decoded request is rebuilt with a `Content-Length`
+    /// header instead of the `Transfer-Encoding` header, and any trailer fields are added to the headers (except for
+    /// the ones which can't be trailers, such as `Content-Length` and `Host`, which are dropped).
+    async fn read_chunked(&mut self, head: String, body_start: usize) -> Result<Vec<u8>, ReadError> {
+        let mut body = Vec::<u8>::new();
+        let mut position = body_start;
+
+        // Read each chunk - a hex size line (which may have extensions after a `;`), the data, then a CRLF
+        loop {
+            let line_end = self.read_line(position).await?;
+            let size_line = String::from_utf8_lossy(&self.buffer[position..line_end]).to_string();
+            let size = size_line.split(';').next().unwrap_or("").trim();
+            // Only hex digits are allowed - `from_str_radix` would also take a leading `+`
+            if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return Err(ReadError::BadRequest("invalid chunk size"));
+            }
+            let size = usize::from_str_radix(size, 16).map_err(|_| ReadError::BadRequest("invalid chunk size"))?;
+            position = line_end + 2;
+
+            if size == 0 {
+                break;
+            }
+            if size > self.max_body_size - body.len() {
+                return Err(ReadError::BodyTooLarge(head.into_bytes()));
+            }
+
+            while self.buffer.len() < position + size + 2 {
+                if self.fill_buffer().await? == 0 {
+                    return Err(ReadError::Closed);
+                }
+            }
+            if &self.buffer[position + size..position + size + 2] != b"\r\n" {
+                return Err(ReadError::BadRequest("chunk data not followed by CRLF"));
+            }
+            body.extend(&self.buffer[position..position + size]);
+            position += size + 2;
+        }
+
+        // The last chunk is followed by optional trailer fields, and an empty line
+        let mut trailers = Vec::new();
+        let mut trailers_len = 0;
+        loop {
+            let line_end = self.read_line(position).await?;
+            let line = String::from_utf8_lossy(&self.buffer[position..line_end]).to_string();
+            position = line_end + 2;
+
+            if line.is_empty() {
+                break;
+            }
+            trailers_len += line.len();
+            if trailers_len > self.max_header_size {
+                return Err(ReadError::HeadersTooLarge);
+            }
+            let (name, _) = headers::parse_line(&line).map_err(ReadError::BadRequest)?;
+            if FORBIDDEN_TRAILERS.iter().any(|forbidden| forbidden.eq_ignore_ascii_case(name)) {
+                continue;
+            }
+            trailers.push(line);
+        }
+
+        self.buffer.drain(..position);
+
+        // Rebuild the request, as if the body was sent with a `Content-Length`
+        let mut lines: Vec<&str> = head.trim_end_matches("\r\n").split("\r\n").collect();
+        // The headers were checked in `read_to_vec`, so every line after the request line is a valid header
+        let request_line = lines.remove(0);
+        lines.retain(|line| !matches!(headers::parse_line(line), Ok((name, _)) if name.eq_ignore_ascii_case("Transfer-Encoding")));
+        lines.insert(0, request_line);
+        let content_length = format!("Content-Length: {}", body.len());
+        lines.extend(trailers.iter().map(|trailer| trailer.as_str()));
+        lines.push(&content_length);
+
+        let mut request = format!("{}\r\n\r\n", lines.join("\r\n")).into_bytes();
+        request.extend(body);
+
+        Ok(request)
+    }
+
+    /// # Read Line
+    ///
+    /// Read until there is a CRLF in the buffer at or after `start`, returning the position of the CRLF.
+    async fn read_line(&mut self, start: usize) -> Result<usize, ReadError> {
+        let mut searched = start;
+        loop {
+            if let Some(end) = self.buffer[searched..].windows(2).position(|window| window == b"\r\n") {
+                return Ok(searched + end);
+            }
+            // Lines in a chunked body are short - don't let a client make us buffer forever
+            if self.buffer.len() - start > self.max_header_size {
+                return Err(ReadError::BadRequest("chunk line too long"));
+            }
+            searched = self.buffer.len().saturating_sub(1).max(start);
+            if self.fill_buffer().await? == 0 {
+                return Err(ReadError::Closed);
+            }
+        }
+    }
+
+    /// # Write Continue
+    ///
+    /// Tell a client which sent `Expect: 100-continue` to go ahead and send the body
+    async fn write_continue(&mut self) -> Result<(), ReadError> {
+        self.write_bytes(b"HTTP/1.1 100 Continue\r\n\r\n".to_vec())
+            .await
+            .map_err(|e| ReadError::Io(io::Error::other(e.to_string())))
+    }
+
+    /// # Fill Buffer
+    ///
+    /// Do a single read from the stream, appending whatever arrived to our buffer.
+    ///
+    /// Returns the number of bytes read - 0 means the client closed the connection.
+    async fn fill_buffer(&mut self) -> Result<usize, io::Error> {
+        self.buffer.reserve(self.read_buffer_size);
 
         // We need to check if we are using TLS or not, as we need to read differently
         // (TLS uses a different read function, and does not support try_read)
@@ -79,73 +350,60 @@ impl Connection {
 
                     // Try to read data, this may still fail with `WouldBlock`
                     // if the readiness event is a false positive.
-                    match stream.try_read_buf(&mut buffer) {
-                        Ok(0) => break, // No data recieved
-                        Ok(_) => {
-                            break; // we recieved some data, just break the loop and return it
+                    match stream.try_read_buf(&mut self.buffer) {
+                        Ok(n) => {
+                            return Ok(n); // we recieved some data (or the stream closed), return how much
                         }
                         Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                             continue; // The IO is busy and would block - just try again
                         }
                         Err(e) => {
-                            return Err(e.into()); // Some other error - quit
+                            return Err(e); // Some other error - quit
                         }
                     }
                 }
-                let buffer: Vec<u8> = buffer
-                    .iter()
-                    .filter(|x| **x != 0x0u8)
-                    .map(|x| *x)
-                    .collect(); // Remove all empty bytes (to avoid trailing whitespaces)
-
-                return Ok(buffer) // return our vector;
-
             },
             ConnectionType::TLS(_) => {
-                // Unfortunately, the TLS stream doesn't implement `AsyncRead`
-                // so we have to use the `split` combinator to split the stream
-                // This is a bit of a hack, but it works. We also lose 
-                // async/await support, but that's ok for now.
-                let tls_stream = self.tls_stream.take().unwrap();
-                let (mut reader, writer) = split(tls_stream);
-
-                // Try to read data, this may still fail with `WouldBlock`
-                // if the readiness event is a false positive.
-                reader.read_buf(&mut buffer).await.unwrap();
-
-                let buffer: Vec<u8> = buffer
-                    .iter()
-                    .filter(|x| **x != 0x0u8)
-                    .map(|x| *x)
-                    .collect(); // Remove all empty bytes (to avoid trailing whitespaces)
+                // Read straight from the stream, rather than splitting it - reads are cancelled when a connection
+                // sits idle for too long, and a cancelled split would lose the stream
+                let tls_stream = self
+                    .tls_stream
+                    .as_mut()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "the TLS stream was lost"))?;
 
-                // Reconnect the stream
-                self.tls_stream = Some(reader.unsplit(writer));
-
-                return Ok(buffer) // return our vector;
+                tls_stream.read_buf(&mut self.buffer).await
             }
-        };
+        }
     }
 
     /// # Write String
     ///
     /// Write a `String` value to the `TcpStream`. Returns a `Result` as we cannot guarantee a successful write.
     pub async fn write_string(&mut self, data: String) -> Result<(), Box<dyn Error>> {
+        self.write_bytes(data.into_bytes()).await
+    }
+
+    /// # Write Bytes
+    ///
+    /// Write bytes to the TCP stream, useful for sending data for things such as images or downloadable binary files
+    pub async fn write_bytes(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
         match self.connection_type{
             ConnectionType::Plain(ref mut stream) => {
                 let stream = stream.as_mut().unwrap();
 
-                loop {
+                let mut written = 0;
+                while written < data.len() {
                     // Wait for the socket to be writable
                     stream.writable().await?;
         
                     // See `read_to_vec` for more explaination what happens here
                     //
                     // Try to write data, this may still fail with `WouldBlock`
-                    // if the readiness event is a false positive.
-                    match stream.try_write(data.as_bytes()) {
-                        Ok(_) => {
-                            break;
+                    // if the readiness event is a false positive. The socket may
+                    // only take part of the data, so keep going until it's all written.
+                    match stream.try_write(&data[written..]) {
+                        Ok(n) => {
+                            written += n;
                         }
                         Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                             continue;
@@ -156,76 +414,146 @@ impl Connection {
                     }
                 }
             },
-            ConnectionType::TLS(_) =>{
-                // Unfortunately, the TLS stream doesn't implement `AsyncWrite`
-                // so we have to use the `sink` combinator to split the stream
-                // This is a bit of a hack, but it works. We also lose 
-                // async/await support, but that's ok for now.
-                let tls_stream = self.tls_stream.take().unwrap();
-                let (reader, mut writer) = split(tls_stream);
-
-                // Try to write data, this may still fail with `WouldBlock`
-                // if the readiness event is a false positive.
-                writer.write_all(data.as_bytes()).await?;
+            ConnectionType::TLS(_) => {
+                // Write straight to the stream, as in `fill_buffer` - writes can be cancelled (eg, a `100 Continue`
+                // when the request times out), and the stream must still be there for the response after
+                let tls_stream = self
+                    .tls_stream
+                    .as_mut()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "the TLS stream was lost"))?;
 
-                // Reconnect the stream
-                self.tls_stream = Some(reader.unsplit(writer));
+                tls_stream.write_all(&data).await?;
+                // The stream may hold on to some of the data until it is flushed
+                tls_stream.flush().await?;
             }
-        }
+        };
 
         Ok(())
     }
 
-    /// # Write Bytes
+    /// # Write Response
     ///
-    /// Write bytes to the TCP stream, useful for sending data for things such as images or downloadable binary files
-    pub async fn write_bytes(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
-        match self.connection_type{
-            ConnectionType::Plain(ref mut stream) => {
-                let stream = stream.as_mut().unwrap();
+    /// Write a `Response` to the stream, leaving off the body if `include_body` is false (for HEAD requests).
+    ///
+    /// File bodies are streamed from disk in blocks, so that large files are never held in memory all at once.
+    /// If a file ends early, an error is returned - the client has already been promised the full length,
+    /// so the connection must be closed.
+    pub async fn write_response(&mut self, response: Response, include_body: bool) -> Result<(), Box<dyn Error>> {
+        if !include_body || !response.status().allows_body() || !response.body().is_streamed() {
+            return self.write_bytes(response.serialize(include_body)).await;
+        }
 
-                loop {
-                    // Wait for the socket to be writable
-                    stream.writable().await?;
-        
-                    // See `read_to_vec` for more explaination what happens here
-                    //
-                    // Try to write data, this may still fail with `WouldBlock`
-                    // if the readiness event is a false positive.
-                    match stream.try_write(&data) {
-                        Ok(_) => {
-                            break;
-                        }
-                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                            continue;
-                        }
-                        Err(e) => {
-                            return Err(e.into());
-                        }
-                    }
+        // Bytes waiting to be sent, so the head and small parts go out along with the next block of a file
+        let mut pending = response.serialize_head();
+        for piece in response.body().pieces() {
+            match piece {
+                Body::Text(text) => pending.extend(text.as_bytes()),
+                Body::Bytes(bytes) => pending.extend(bytes),
+                Body::File { path, offset, len } => {
+                    pending = self.write_file(pending, path, *offset, *len).await?;
                 }
-            },
-            ConnectionType::TLS(_) => {
-                // Unfortunately, the TLS stream doesn't implement `AsyncWrite`
-                // so we have to use the `sink` combinator to split the stream
-                // This is a bit of a hack, but it works. We also lose 
-                // async/await support, but that's ok for now.
-                let tls_stream = self.tls_stream.take().unwrap();
-                let (reader, mut writer) = split(tls_stream);
+                Body::Empty | Body::Parts(_) => {}
+            }
+        }
+
+        if !pending.is_empty() {
+            self.write_bytes(pending).await?;
+        }
 
-                // Try to write data, this may still fail with `WouldBlock`
-                // if the readiness event is a false positive.
-                writer.write_all(&data).await?;
+        Ok(())
+    }
 
-                // Reconnect the stream
-                self.tls_stream = Some(reader.unsplit(writer));
+    /// # Write File
+    ///
+    /// Stream `len` bytes of a file, starting at `offset`, after the `pending` bytes. Returns the bytes still waiting to be sent.
+    async fn write_file(&mut self, mut pending: Vec<u8>, path: &Path, offset: u64, len: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut file = tokio::fs::File::open(path).await?;
+        file.seek(io::SeekFrom::Start(offset)).await?;
+        let mut file = file.take(len);
+
+        let mut remaining = len;
+        while remaining > 0 {
+            let start = pending.len();
+            pending.resize(start + FILE_BLOCK_SIZE.min(remaining as usize), 0);
+            let read = file.read(&mut pending[start..]).await?;
+            if read == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
             }
-        };
+            pending.truncate(start + read);
+            remaining -= read as u64;
 
-        Ok(())
+            self.write_bytes(std::mem::take(&mut pending)).await?;
+        }
+
+        Ok(pending)
+    }
+}
+
+/// Find where the request head ends (the position of the empty line after the headers)
+fn find_header_end(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(4).position(|window| window == b"\r\n\r\n")
+}
+
+/// Get the length of the request body from the `Content-Length` header. Requests without one have no body.
+fn content_length(headers: &Headers) -> Result<usize, ReadError> {
+    let mut length = None;
+    for value in headers.get_all("Content-Length") {
+        // Only digits are allowed - `parse` would also take a leading `+`, which a proxy might read differently
+        if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(ReadError::BadRequest("invalid Content-Length"));
+        }
+        let value = value
+            .parse::<usize>()
+            .map_err(|_| ReadError::BadRequest("invalid Content-Length"))?;
+        // Differing lengths would let a request be read in more than one way, so reject them
+        if length.is_some_and(|length| length != value) {
+            return Err(ReadError::BadRequest("conflicting Content-Length headers"));
+        }
+        length = Some(value);
+    }
+
+    Ok(length.unwrap_or(0))
+}
+
+/// Check whether the body is sent with chunked transfer encoding.
+///
+/// Chunked has to be the last encoding applied, otherwise we can't tell where the body ends. A request which also
+/// has a `Content-Length`, or which is HTTP/1.0 (which has no transfer encodings), could be framed differently by
+/// a proxy in front of us, letting a second request be smuggled in its body - so those are rejected.
+fn is_chunked(request_line: &str, headers: &Headers) -> Result<bool, ReadError> {
+    if !headers.contains("Transfer-Encoding") {
+        return Ok(false);
+    }
+    if headers.contains("Content-Length") {
+        return Err(ReadError::BadRequest("both Content-Length and Transfer-Encoding were sent"));
+    }
+    // Requests without a version are HTTP/1.0, as in `Request::get_version`
+    if request_line.split(' ').nth(2) != Some("HTTP/1.1") {
+        return Err(ReadError::BadRequest("Transfer-Encoding was sent with HTTP/1.0"));
+    }
+
+    let encodings: Vec<String> = headers
+        .get_all("Transfer-Encoding")
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .map(|encoding| encoding.trim().to_ascii_lowercase())
+        .filter(|encoding| !encoding.is_empty())
+        .collect();
+
+    match encodings.last() {
+        Some(encoding) if encoding == "chunked" => Ok(true),
+        _ => Err(ReadError::BadRequest("unsupported transfer encoding")),
     }
 }
 
+/// Check whether the client is waiting for a `100 Continue` before it sends the body
+fn expects_continue(headers: &Headers) -> bool {
+    headers
+        .get_all("Expect")
+        .iter()
+        .any(|value| value.eq_ignore_ascii_case("100-continue"))
+}
+
 /// Trim the ends of the `String` we got from the `TcpStream` so we don't waste buffer space with whitespace
 fn trim_newline(s: &mut String) {
     if s.ends_with('\n') {
@@ -235,3 +563,113 @@ fn trim_newline(s: &mut String) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Send `request` to a new connection (closing it afterwards), and read the first request from it
+    async fn read_request(request: &[u8]) -> Result<Vec<u8>, ReadError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        client.write_all(request).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut connection = Connection::new(ConnectionType::Plain(Some(stream)), 1024, 8192, 1024);
+        connection.read_to_vec().await
+    }
+
+    fn headers(lines: &[&str]) -> Headers {
+        Headers::try_parse(lines.iter().copied()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_body_by_content_length() {
+        let request = read_request(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n").await.unwrap();
+        assert_eq!(request, b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[tokio::test]
+    async fn decodes_chunked_bodies() {
+        let request = read_request(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;name=value\r\nhello\r\n6\r\n world\r\n0\r\n\r\n")
+            .await
+            .unwrap();
+        assert_eq!(request, b"POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world");
+    }
+
+    #[tokio::test]
+    async fn drops_forbidden_trailers() {
+        let request = read_request(
+            b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\nX-Checksum: 1\r\nContent-Length: 99\r\nHost: b\r\n\r\n",
+        )
+        .await
+        .unwrap();
+        assert_eq!(request, b"POST / HTTP/1.1\r\nHost: a\r\nX-Checksum: 1\r\nContent-Length: 3\r\n\r\nabc");
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_chunk_sizes() {
+        for size in ["+5", "-5", "0x5", " ", "fffffffffffffffffffff"] {
+            let request = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}\r\nhello\r\n0\r\n\r\n", size);
+            assert!(matches!(read_request(request.as_bytes()).await, Err(ReadError::BadRequest(_))), "{}", size);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_content_length_with_transfer_encoding() {
+        let request = read_request(
+            b"POST /one HTTP/1.1\r\nContent-Length: 30\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /smuggled HTTP/1.1\r\n\r\n",
+        )
+        .await;
+        assert!(matches!(request, Err(ReadError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_transfer_encoding_on_http_1_0() {
+        let request = read_request(b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n").await;
+        assert!(matches!(request, Err(ReadError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_whitespace_before_colon_and_folded_lines() {
+        let request = read_request(b"POST / HTTP/1.1\r\nTransfer-Encoding : chunked\r\n\r\n0\r\n\r\n").await;
+        assert!(matches!(request, Err(ReadError::BadRequest(_))));
+
+        let request = read_request(b"GET / HTTP/1.1\r\nX-A: a\r\n X-B: b\r\n\r\n").await;
+        assert!(matches!(request, Err(ReadError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn skips_empty_lines_before_the_request() {
+        let request = read_request(b"\r\n\r\nGET / HTTP/1.1\r\n\r\n").await.unwrap();
+        assert_eq!(request, b"GET / HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn parses_content_length() {
+        assert_eq!(content_length(&headers(&[])).unwrap(), 0);
+        assert_eq!(content_length(&headers(&["Content-Length: 42"])).unwrap(), 42);
+        assert_eq!(content_length(&headers(&["Content-Length: 42", "content-length: 42"])).unwrap(), 42);
+    }
+
+    #[test]
+    fn rejects_invalid_content_length() {
+        for value in ["+5", "-5", "5 5", "0x5", "", "99999999999999999999999"] {
+            let header = format!("Content-Length: {}", value);
+            assert!(content_length(&headers(&[&header])).is_err(), "{}", value);
+        }
+        assert!(content_length(&headers(&["Content-Length: 5", "Content-Length: 6"])).is_err());
+    }
+
+    #[test]
+    fn chunked_must_be_the_last_encoding() {
+        assert!(!is_chunked("POST / HTTP/1.1", &headers(&[])).unwrap());
+        assert!(is_chunked("POST / HTTP/1.1", &headers(&["Transfer-Encoding: gzip, chunked"])).unwrap());
+        assert!(is_chunked("POST / HTTP/1.1", &headers(&["Transfer-Encoding: chunked, gzip"])).is_err());
+        assert!(is_chunked("POST / HTTP/1.1", &headers(&["Transfer-Encoding: identity"])).is_err());
+        assert!(is_chunked("POST /", &headers(&["Transfer-Encoding: chunked"])).is_err());
+    }
+}
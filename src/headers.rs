@@ -0,0 +1,162 @@
+/// # Headers
+///
+/// A map of HTTP headers.
+///
+/// Header names are matched case-insensitively (so `content-type` finds `Content-Type`), and a
+/// header can have more than one value, as clients are allowed to send the same header many times.
+///
+/// The headers are kept in the order they were added, with the names exactly as they were given.
+///
+/// **Example**
+/// ```
+/// # use micro_http_async::Headers;
+/// let mut headers = Headers::new();
+/// headers.insert("Accept", "text/html");
+/// headers.insert("accept", "application/json");
+///
+/// assert_eq!(headers.get("ACCEPT"), Some("text/html"));
+/// assert_eq!(headers.get_all("Accept"), vec!["text/html", "application/json"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    /// The `(name, value)` pairs of every header
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// # New
+    ///
+    /// Create an empty header map
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// # Parse
+    ///
+    /// Parse header lines (eg, `Content-Type: text/html`) into a header map.
+    ///
+    /// Lines which aren't valid headers (such as the request line) are skipped. Values are trimmed,
+    /// but otherwise left exactly as they were sent.
+    pub fn parse<'a>(lines: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut headers = Self::new();
+        for (name, value) in lines.into_iter().filter_map(|line| parse_line(line).ok()) {
+            headers.insert(name, value);
+        }
+        headers
+    }
+
+    /// # Try Parse
+    ///
+    /// Parse the header lines of a request, failing on any line which isn't a valid header (see `parse_line`).
+    ///
+    /// Headers decide how a request is framed, so a line which other servers might read differently must be rejected, not skipped.
+    pub(crate) fn try_parse<'a>(lines: impl IntoIterator<Item = &'a str>) -> Result<Self, &'static str> {
+        let mut headers = Self::new();
+        for line in lines {
+            let (name, value) = parse_line(line)?;
+            headers.insert(name, value);
+        }
+        Ok(headers)
+    }
+
+    /// # Insert
+    ///
+    /// Add a header. If a header with the same name already exists, both values are kept.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// # Set
+    ///
+    /// Set a header, replacing any values it already had.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// # Remove
+    ///
+    /// Remove every value of a header
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    /// # Get
+    ///
+    /// Get the first value of a header, if it was sent
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// # Get All
+    ///
+    /// Get every value of a header, in the order they were sent
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// # Contains
+    ///
+    /// Check whether a header was sent
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// # Iter
+    ///
+    /// Iterate over every `(name, value)` pair
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// # Len
+    ///
+    /// The number of header values
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// # Is Empty
+    ///
+    /// Check whether there are no headers
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Split a header line into its name and value.
+///
+/// The name must be a token, directly followed by the colon - whitespace before the colon isn't allowed, and neither
+/// are lines starting with whitespace (obsolete line folding, which continues the line before). The value has the
+/// whitespace around it trimmed.
+pub(crate) fn parse_line(line: &str) -> Result<(&str, &str), &'static str> {
+    if line.starts_with([' ', '\t']) {
+        return Err("folded header lines are not supported");
+    }
+    let (name, value) = line.split_once(':').ok_or("header line has no colon")?;
+    if !is_valid_header_name(name) {
+        return Err("invalid header name");
+    }
+
+    Ok((name, value.trim_matches([' ', '\t'])))
+}
+
+/// Whether `name` can be a header name - it must be a non-empty token (no whitespace, separators or control characters)
+pub(crate) fn is_valid_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
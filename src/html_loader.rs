@@ -1,4 +1,4 @@
-use crate::Response;
+use crate::{Response, StatusCode};
 use std::collections::HashMap;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
@@ -55,6 +55,7 @@ pub type Vars = HashMap<String, Variable>;
 /// ```
 ///
 /// ```rust
+/// # use micro_http_async::{Variable, Vars};
 /// let mut vars = Vars::new();
 /// vars.insert("test_var".to_string(), Variable::String("Test".to_string()));
 /// ```
@@ -71,29 +72,15 @@ impl HtmlConstructor {
     ///
     /// Takes in a file path (to the HTML file) and a `Vars` type.
     ///
-    /// Constructs the HTML page, returning a string value (also assigns all dynamic variables if any)
-    pub async fn construct_page(response_code: Response, path: &str, vars: Vars) -> String {
+    /// Constructs the HTML page, returning a `Response` (also assigns all dynamic variables if any)
+    ///
+    /// Any status code can be used (eg, `200` or `StatusCode::NOT_FOUND`).
+    pub async fn construct_page(response_code: impl Into<StatusCode>, path: &str, vars: Vars) -> Response {
         let file = FileLoader::load_template(path).await;
 
         let file = HtmlConstructor::set_dynamic_vars(file, vars);
 
-        // This should be changed over to support all response types
-        let header_code = match response_code {
-            Response::Ok => {
-                format!("HTTP/1.1 {} {}\r\n\r\n", 200, "OK")
-            }
-            Response::Redirect => {
-                format!("HTTP/1.1 {} {}\r\n\r\n", 301, "MOVED PERMANENTLY")
-            }
-            Response::ClientErr => {
-                format!("HTTP/1.1 {} {}\r\n\r\n", 404, "NOT FOUND")
-            }
-            Response::ServerErr => {
-                format!("HTTP/1.1 {} {}\r\n\r\n", 500, "INTERNAL SERVER ERROR")
-            }
-        };
-        let file = format!("{}{}", header_code, file);
-        return file;
+        Response::new(response_code).html(file)
     }
 
     /// # Set Dynamic Vars
@@ -116,7 +103,7 @@ impl HtmlConstructor {
                     file = file.replace(&var_to_replace, &v.to_string());
                 }
                 Variable::String(v) => {
-                    file = file.replace(&var_to_replace, &v);
+                    file = file.replace(&var_to_replace, v);
                 }
             };
         }
@@ -3,13 +3,13 @@
 //! A small, lightweight crate using async to serve web pages or web apis with high performance and low overhead.
 //! ## How do I use it?
 //! Firstly, install the crate and dependencies:
-//! ```
+//! ```toml
 //! [dependencies]
 //! micro_http_async = "*"
 //! tokio = "1.11.0"
 //! ```
 //! And if you want to support JSON:
-//! ```
+//! ```toml
 //! serde_json = "1.0"
 //! serde = {version = "1.0", features = ["derive"]}
 //! ```
@@ -23,7 +23,7 @@
 //! [Examples](https://github.com/dimitribobkov/micro-http-async/tree/master/examples)
 //! 
 //! This crate aims only to simplify web api or lightweight web creation - not intended to run full scale web apps like chatrooms
-//! or other high intensity applications. It implements a simple asynchronous routing system (Made using hash maps for speed and efficiency)
+//! or other high intensity applications. It implements a simple asynchronous routing system (Made using a radix tree for speed and efficiency)
 //! as well as asynchronous file loading and more.
 //! 
 //! It also supports TLS if security is a requirement through the [rustls](https://github.com/rustls/rustls) and [tokio-rustls](https://github.com/rustls/rustls) crates.
@@ -34,26 +34,38 @@
 
 #![doc(test(attr(deny(warnings))))]
 #![doc(test(no_crate_inject))]
-// This is a workaround while we wait for the feature to become stable
-#![feature(write_all_vectored)]
-#![feature(string_remove_matches)]
 
+mod builder;
 mod connection;
+mod date;
+mod headers;
 mod html_loader;
 mod json_response;
+mod listing;
+mod mime;
+mod path;
+mod range;
 mod request;
 mod response;
+mod router;
 mod routes;
 mod server;
+mod static_files;
+mod status;
 
-pub use connection::Connection;
+pub use builder::{BuildError, HttpServerBuilder};
+pub use connection::{Connection, ReadError};
+pub use headers::Headers;
 pub use html_loader::{FileLoader, HtmlConstructor, Variable, Vars};
 pub use json_response::JSONResponse;
 pub use request::{HttpMethod, Request};
-pub use response::Response;
+pub use response::{Body, HttpError, IntoResponse, Response};
+pub use router::RouteError;
 pub use routes::Routes;
-pub use routes::{DataType, Route, RouteDef};
-pub use server::HttpServer;
+pub use routes::{ErrorRoute, ErrorRouteDef, Route, RouteDef};
+pub use server::{HttpServer, ServerError, ShutdownHandle};
+pub use static_files::StaticFiles;
+pub use status::StatusCode;
 
 /* Define Macros */
 
@@ -67,3 +79,15 @@ macro_rules! create_route {
         Route::new(Box::new($inc))
     }};
 }
+
+/// # Create Error Route
+///
+/// This macro takes in an async function which takes a `Request` and a `HttpError`, and outputs an ErrorRoute
+/// that can be used as an error handler
+#[macro_export]
+macro_rules! create_error_route {
+    ($inc:expr) => {{
+        use micro_http_async::ErrorRoute;
+        ErrorRoute::new(Box::new($inc))
+    }};
+}
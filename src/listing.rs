@@ -0,0 +1,123 @@
+use crate::date::format_http_date;
+use crate::path::percent_encode;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// # Entry
+///
+/// A file or directory shown in a directory listing
+pub(crate) struct Entry {
+    /// The name of the file or directory
+    name: String,
+    /// Whether it is a directory
+    is_dir: bool,
+    /// The size of the file in bytes (directories have no size)
+    size: Option<u64>,
+    /// When it was last modified, if the OS keeps track
+    modified: Option<SystemTime>,
+}
+
+/// Read the entries of a directory for a listing, directories first, then sorted by name.
+///
+/// Hidden entries (names starting with `.`, unless `show_hidden`), entries which can't be read (such as broken symlinks),
+/// and symlinks which point outside of `root` (which must be canonical) are left out.
+pub(crate) async fn read_entries(directory: &Path, root: &Path, show_hidden: bool) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut read_dir = tokio::fs::read_dir(directory).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !show_hidden && name.starts_with('.') {
+            continue;
+        }
+        // Follow symlinks, so they are listed as whatever they point to (unless that's outside the root, or hidden)
+        let path = match tokio::fs::canonicalize(entry.path()).await {
+            Ok(path) if path.starts_with(root) => path,
+            _ => continue,
+        };
+        if !show_hidden && is_hidden(path.strip_prefix(root).unwrap_or(&path)) {
+            continue;
+        }
+        let metadata = match tokio::fs::metadata(path).await {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+
+        entries.push(Entry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { None } else { Some(metadata.len()) },
+            modified: metadata.modified().ok(),
+        });
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    Ok(entries)
+}
+
+/// Whether any part of a path is hidden (its name starts with `.`)
+pub(crate) fn is_hidden(path: &Path) -> bool {
+    path.components().any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+/// Render a directory listing as a HTML page. `path` is the (decoded) request path of the directory,
+/// and `has_parent` adds a link to the directory above it.
+pub(crate) fn render_html(path: &str, entries: &[Entry], has_parent: bool) -> String {
+    let title = format!("Index of {}", escape_html(path));
+    let mut rows = String::new();
+    if has_parent {
+        rows.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            percent_encode(&entry.name),
+            suffix,
+            escape_html(&entry.name),
+            suffix,
+            entry.size.map(|size| size.to_string()).unwrap_or_default(),
+            entry.modified.map(format_http_date).unwrap_or_default()
+        ));
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n\
+         <table>\n<tr><th>Name</th><th>Size</th><th>Last Modified</th></tr>\n{rows}</table>\n</body>\n</html>\n",
+        title = title,
+        rows = rows
+    )
+}
+
+/// Render a directory listing as JSON - the path of the directory, and the name, type, size and
+/// modification time of each entry
+pub(crate) fn render_json(path: &str, entries: &[Entry]) -> serde_json::Value {
+    let entries: Vec<serde_json::Value> = entries
+        .iter()
+        .map(|entry| {
+            serde_json::json!({
+                "name": entry.name,
+                "type": if entry.is_dir { "directory" } else { "file" },
+                "size": entry.size,
+                "modified": entry.modified.map(format_http_date),
+            })
+        })
+        .collect();
+
+    serde_json::json!({ "path": path, "entries": entries })
+}
+
+/// Escape the characters which have a meaning in HTML, so file names can't inject markup into the page
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The content types known out of the box, by file extension
+const BUILTIN: &[(&str, &str)] = &[
+    // Text
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("md", "text/markdown"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    // Images
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("ico", "image/x-icon"),
+    ("svg", "image/svg+xml"),
+    // Fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    // Audio and video
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    // Everything else
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+];
+
+/// # Mime Types
+///
+/// A table of content types, looked up by file extension. Starts out with the common web types, and can be extended.
+#[derive(Debug, Clone)]
+pub(crate) struct MimeTypes {
+    /// The content type of each extension, with the extensions in lower case
+    types: HashMap<String, String>,
+    /// The content type used for files whose extension isn't in the table
+    default: String,
+}
+
+impl Default for MimeTypes {
+    fn default() -> Self {
+        Self {
+            types: BUILTIN
+                .iter()
+                .map(|(extension, mime)| (extension.to_string(), mime.to_string()))
+                .collect(),
+            default: "application/octet-stream".to_string(),
+        }
+    }
+}
+
+impl MimeTypes {
+    /// # Insert
+    ///
+    /// Add an extension to the table, replacing its content type if it already had one
+    pub(crate) fn insert(&mut self, extension: &str, mime: &str) {
+        self.types
+            .insert(extension.trim_start_matches('.').to_ascii_lowercase(), mime.to_string());
+    }
+
+    /// # Set Default
+    ///
+    /// Set the content type used for files whose extension isn't in the table
+    pub(crate) fn set_default(&mut self, mime: &str) {
+        self.default = mime.to_string();
+    }
+
+    /// # Content Type
+    ///
+    /// The value of the `Content-Type` header for a file. Text types get `charset=utf-8` added.
+    pub(crate) fn content_type(&self, path: &Path) -> String {
+        let mime = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| self.types.get(&extension.to_ascii_lowercase()))
+            .unwrap_or(&self.default);
+
+        if is_text(mime) && !mime.contains("charset") {
+            format!("{}; charset=utf-8", mime)
+        } else {
+            mime.clone()
+        }
+    }
+}
+
+/// Check whether a content type is text, which browsers need a charset for
+fn is_text(mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or("").trim();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || essence == "application/json"
+        || essence == "application/xml"
+        || essence == "application/javascript"
+}
@@ -0,0 +1,102 @@
+use crate::router::RouteError;
+
+/// # Segment
+///
+/// A single `/` separated part of a route path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Segment {
+    /// A segment which must match exactly, eg `users`
+    Static(String),
+    /// A segment which matches anything, and captures it under a name, eg `:id`
+    Param(String),
+    /// Matches the rest of the path, and captures it under a name, eg `*path`. Always the last segment.
+    Wildcard(String),
+}
+
+/// # Path Pattern
+///
+/// A parsed route path, such as `/users/:id` or `/files/*path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PathPattern {
+    /// The segments of the path, in order
+    pub(crate) segments: Vec<Segment>,
+}
+
+impl PathPattern {
+    /// # Parse
+    ///
+    /// Parse a route path. Segments starting with `:` are parameters, and a segment starting with `*` is a
+    /// wildcard which captures the rest of the path, so it must be the last segment.
+    ///
+    /// Fails if a parameter or wildcard has no name, or if anything follows a wildcard.
+    pub(crate) fn parse(path: &str) -> Result<Self, RouteError> {
+        let invalid = |reason| RouteError::InvalidPattern {
+            route: path.to_string(),
+            reason,
+        };
+
+        let mut segments = Vec::new();
+        for segment in split_path(path) {
+            if matches!(segments.last(), Some(Segment::Wildcard(_))) {
+                return Err(invalid("a wildcard must be the last segment"));
+            }
+            if let Some(name) = segment.strip_prefix(':') {
+                if name.is_empty() {
+                    return Err(invalid("a parameter must have a name"));
+                }
+                segments.push(Segment::Param(name.to_string()));
+            } else if let Some(name) = segment.strip_prefix('*') {
+                if name.is_empty() {
+                    return Err(invalid("a wildcard must have a name"));
+                }
+                segments.push(Segment::Wildcard(name.to_string()));
+            } else {
+                segments.push(Segment::Static(segment.to_string()));
+            }
+        }
+
+        Ok(Self { segments })
+    }
+}
+
+/// Split a path into its segments. Empty segments are skipped, so `/users/42/` is the same as `/users/42`.
+pub(crate) fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+/// Decode `%XX` escapes in a captured path segment. Invalid escapes are left as they are.
+pub(crate) fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' && index + 2 < bytes.len() {
+            if let (Some(high), Some(low)) = (hex_value(bytes[index + 1]), hex_value(bytes[index + 2])) {
+                decoded.push(high * 16 + low);
+                index += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[index]);
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Encode a path segment with `%XX` escapes, so it can be used in a URL. Only letters, digits and `-._~` are left as they are.
+pub(crate) fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// The value of a single hex digit
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
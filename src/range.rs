@@ -0,0 +1,179 @@
+/// The most ranges a single request can ask for. Requests for more are answered with the whole file, as
+/// lots of tiny ranges cost far more to send than the file itself (as do overlapping ranges which add up
+/// to more than the file).
+const MAX_RANGES: usize = 16;
+
+/// # Byte Range
+///
+/// A range of bytes in a file, from `start` to `end` inclusive (as they are written in `Content-Range`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ByteRange {
+    /// The first byte in the range
+    pub(crate) start: u64,
+    /// The last byte in the range
+    pub(crate) end: u64,
+}
+
+impl ByteRange {
+    /// # Len
+    ///
+    /// The number of bytes in the range
+    pub(crate) fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// # Content Range
+    ///
+    /// The value of the `Content-Range` header for this range of a file which is `file_len` bytes long
+    pub(crate) fn content_range(&self, file_len: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, file_len)
+    }
+}
+
+/// # Ranges
+///
+/// What a `Range` header asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Ranges {
+    /// The header can't be used (it is malformed, uses a unit other than bytes, or asks for too many ranges),
+    /// so it is ignored and the whole file is sent
+    Full,
+    /// The ranges of the file which were asked for, in the order they were asked for
+    Partial(Vec<ByteRange>),
+    /// None of the ranges overlap the file
+    Unsatisfiable,
+}
+
+/// Parse the value of a `Range` header (eg, `bytes=0-499, -500`) for a file which is `file_len` bytes long.
+///
+/// Ranges which go past the end of the file are cut short, and ranges which start after it are dropped.
+pub(crate) fn parse_range(header: &str, file_len: u64) -> Ranges {
+    let specs = match header.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None => return Ranges::Full,
+    };
+
+    let mut ranges = Vec::new();
+    let mut specs_seen = 0;
+    for spec in specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+        let (start, end) = match spec.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => return Ranges::Full,
+        };
+        specs_seen += 1;
+
+        let range = if start.is_empty() {
+            // A suffix range - the last `end` bytes
+            let suffix: u64 = match end.parse() {
+                Ok(suffix) => suffix,
+                Err(_) => return Ranges::Full,
+            };
+            if suffix == 0 || file_len == 0 {
+                continue;
+            }
+            ByteRange {
+                start: file_len.saturating_sub(suffix),
+                end: file_len - 1,
+            }
+        } else {
+            let start: u64 = match start.parse() {
+                Ok(start) => start,
+                Err(_) => return Ranges::Full,
+            };
+            let end: u64 = match end {
+                "" => u64::MAX,
+                end => match end.parse() {
+                    Ok(end) => end,
+                    Err(_) => return Ranges::Full,
+                },
+            };
+            if end < start {
+                return Ranges::Full;
+            }
+            if start >= file_len {
+                continue;
+            }
+            ByteRange {
+                start,
+                end: end.min(file_len - 1),
+            }
+        };
+
+        ranges.push(range);
+    }
+
+    let total: u64 = ranges.iter().map(ByteRange::len).sum();
+    if specs_seen == 0 || ranges.len() > MAX_RANGES || total > file_len {
+        Ranges::Full
+    } else if ranges.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Partial(ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(parse_range("bytes=0-499", 1000), Ranges::Partial(vec![range(0, 499)]));
+        assert_eq!(parse_range("bytes=500-", 1000), Ranges::Partial(vec![range(500, 999)]));
+        assert_eq!(parse_range("bytes=-200", 1000), Ranges::Partial(vec![range(800, 999)]));
+        assert_eq!(parse_range(" bytes=10-10 ", 1000), Ranges::Partial(vec![range(10, 10)]));
+    }
+
+    #[test]
+    fn clamps_ranges_to_the_file() {
+        assert_eq!(parse_range("bytes=900-5000", 1000), Ranges::Partial(vec![range(900, 999)]));
+        assert_eq!(parse_range("bytes=-5000", 1000), Ranges::Partial(vec![range(0, 999)]));
+        assert_eq!(parse_range("bytes=-5000", 0), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn parses_multiple_ranges() {
+        assert_eq!(
+            parse_range("bytes=0-9, 20-29,-5", 100),
+            Ranges::Partial(vec![range(0, 9), range(20, 29), range(95, 99)])
+        );
+        // Ranges past the end are dropped, the rest are kept
+        assert_eq!(parse_range("bytes=0-9,500-600", 100), Ranges::Partial(vec![range(0, 9)]));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), Ranges::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_unusable_headers() {
+        assert_eq!(parse_range("items=0-9", 1000), Ranges::Full);
+        assert_eq!(parse_range("bytes=", 1000), Ranges::Full);
+        assert_eq!(parse_range("bytes=abc-def", 1000), Ranges::Full);
+        assert_eq!(parse_range("bytes=9-0", 1000), Ranges::Full);
+        assert_eq!(parse_range("bytes=5", 1000), Ranges::Full);
+        assert_eq!(parse_range("bytes=0-99999999999999999999999", 1000), Ranges::Full);
+    }
+
+    #[test]
+    fn ignores_abusive_ranges() {
+        // More ranges than allowed
+        let many: Vec<String> = (0..17).map(|i| format!("{}-{}", i * 2, i * 2)).collect();
+        assert_eq!(parse_range(&format!("bytes={}", many.join(",")), 1000), Ranges::Full);
+        // Overlapping ranges which add up to more than the file
+        assert_eq!(parse_range("bytes=0-999,0-999", 1000), Ranges::Full);
+    }
+
+    #[test]
+    fn content_range() {
+        assert_eq!(range(0, 499).content_range(1000), "bytes 0-499/1000");
+        assert_eq!(range(0, 499).len(), 500);
+    }
+}
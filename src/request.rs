@@ -2,14 +2,16 @@ use std::collections::HashMap;
 
 use regex::Regex;
 
+use crate::Headers;
+
 
 /// # Http Methods
 ///
 /// An enum with the types of method that a user can request
 ///
-/// It is up to the user to specify in the callbacks which
-/// methods to accept
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Routes can be registered for a single method (see [Routes](struct.Routes.html)),
+/// or for any method, in which case it is up to the callback to check the method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum HttpMethod {
     Get,
     Post,
@@ -19,6 +21,32 @@ pub enum HttpMethod {
     Connect,
     Options,
     Trace,
+    Patch,
+}
+
+impl HttpMethod {
+    /// # As Str
+    ///
+    /// Get the method as it's written in a request (eg, `GET`)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Post => "POST",
+            HttpMethod::Head => "HEAD",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Connect => "CONNECT",
+            HttpMethod::Options => "OPTIONS",
+            HttpMethod::Trace => "TRACE",
+            HttpMethod::Patch => "PATCH",
+        }
+    }
+}
+
+impl std::fmt::Display for HttpMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
 }
 
 /// # Request
@@ -27,9 +55,9 @@ pub enum HttpMethod {
 ///
 /// Can be used to gather POST and GET info, user-agent information and more
 ///
-/// If you have custom headers, and want to access them, use `raw_request` to access the
-/// raw unmodified request
-#[derive(Debug)]
+/// If you have custom headers, and want to access them, use `headers` (or the [header](#method.header) function)
+/// to look them up. Header names are case-insensitive.
+#[derive(Debug, Clone)]
 pub struct Request {
     /// Method stores the method used to
     /// make the request
@@ -54,10 +82,25 @@ pub struct Request {
     /// The key of the hashmap is equal to the name of the
     /// form field name.
     pub post_request: HashMap<String, PostRequest>,
-    /// Raw Request stores the raw request without any modifications.
+    /// Params stores the values captured from the path by the route, eg `id` for `/users/:id`.
+    ///
+    /// It is a `HashMap<String, String>`, where the key is the name of the parameter
+    /// (without the `:` or `*`).
+    pub params: HashMap<String, String>,
+    /// Headers stores every header the client sent.
+    ///
+    /// Header names are matched case-insensitively, and may have more than one value.
+    pub headers: Headers,
+    /// Raw Request stores the raw request line and headers without any modifications.
     pub raw_request: Vec<String>,
+    /// Body stores the request body, exactly as the client sent it.
+    ///
+    /// It is kept as raw bytes, so binary uploads aren't corrupted.
+    pub body: Vec<u8>,
     /// Did the request come from a secure connection?
     pub secure: bool,
+    /// The HTTP version the request was made with (eg, `HTTP/1.1`)
+    pub version: String,
 }
 
 impl Request {
@@ -65,22 +108,33 @@ impl Request {
     ///
     /// Create a new request struct.
     ///
-    /// Takes an input string (Which should be
-    /// the request).
+    /// Takes the raw bytes of the request (Which should be
+    /// the request line and headers, followed by the body).
     ///
     /// It will then construct itself and return, ready to use.
-    pub async fn new(request: String, user_addr: std::net::SocketAddr, is_secure: bool) -> Result<Self, &'static str> {
-        let request = Request::split_to_row(request).await;
+    pub async fn new(request: Vec<u8>, user_addr: std::net::SocketAddr, is_secure: bool) -> Result<Self, &'static str> {
+        // The headers are text, but the body may be binary, so they're handled separately
+        let (head, body) = match find_bytes(&request, b"\r\n\r\n") {
+            Some(position) => (&request[..position], request[position + 4..].to_vec()),
+            None => (&request[..], Vec::new()),
+        };
+        let head = String::from_utf8_lossy(head).to_string();
+
+        let request = Request::split_to_row(head).await;
 
         let method = Request::get_method(&request).await;
 
         let uri = Request::get_uri(&request).await;
 
-        let user_agent = Request::get_user_agent(&request).await;
+        let version = Request::get_version(&request).await;
+
+        let headers = Headers::try_parse(request.iter().skip(1).map(|line| line.as_str()))?;
+
+        let user_agent = Request::get_user_agent(&headers).await;
 
         let (get_request, uri) = Request::get_vars(&uri).await;
 
-        let post_request = Request::get_post_request(&request).await;
+        let post_request = Request::get_post_request(&headers, &body).await;
 
         Ok(Self {
             method,
@@ -89,78 +143,142 @@ impl Request {
             user_addr,
             get_request,
             post_request,
+            params: HashMap::new(),
+            headers,
             raw_request: request,
+            body,
             secure: is_secure,
+            version,
         })
     }
 
+    /// # Without Body
+    ///
+    /// Copy the request, leaving out the body (and the form data parsed from it), which may be large
+    pub(crate) fn without_body(&self) -> Self {
+        Self {
+            method: self.method,
+            uri: self.uri.clone(),
+            user_agent: self.user_agent.clone(),
+            user_addr: self.user_addr,
+            get_request: self.get_request.clone(),
+            post_request: HashMap::new(),
+            params: self.params.clone(),
+            headers: self.headers.clone(),
+            raw_request: self.raw_request.clone(),
+            body: Vec::new(),
+            secure: self.secure,
+            version: self.version.clone(),
+        }
+    }
+
+    /// # Param
+    ///
+    /// Get a value captured from the path by the route, eg `request.param("id")` for a route on `/users/:id`
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|value| value.as_str())
+    }
+
+    /// # Header
+    ///
+    /// Get the first value of a header, if the client sent it. The name is case-insensitive.
+    ///
+    /// Use `headers.get_all` to get every value of a header which was sent more than once.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// # Content Type
+    ///
+    /// Get the media type of the body (eg, `application/json`), without any parameters such as the charset
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("Content-Type")
+            .map(|content_type| content_type.split(';').next().unwrap_or("").trim())
+    }
+
+    /// # Content Length
+    ///
+    /// Get the length of the body the client said it sent, if it sent a valid `Content-Length`
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("Content-Length")?.trim().parse().ok()
+    }
+
+    /// # Host
+    ///
+    /// Get the host the request was sent to, from the `Host` header
+    pub fn host(&self) -> Option<&str> {
+        self.header("Host")
+    }
+
+    /// # Authorization
+    ///
+    /// Get the credentials the client sent in the `Authorization` header (eg, `Bearer <token>`)
+    pub fn authorization(&self) -> Option<&str> {
+        self.header("Authorization")
+    }
+
+    /// # Accept
+    ///
+    /// Get the media types the client accepts, from every `Accept` header, in the order they were sent.
+    ///
+    /// Quality values are left on each media type (eg, `text/html;q=0.9`)
+    pub fn accept(&self) -> Vec<&str> {
+        self.headers
+            .get_all("Accept")
+            .into_iter()
+            .flat_map(|accept| accept.split(','))
+            .map(|media_type| media_type.trim())
+            .filter(|media_type| !media_type.is_empty())
+            .collect()
+    }
+
+    /// # Keep Alive
+    ///
+    /// Check whether the client wants the connection to stay open after this request.
+    ///
+    /// HTTP/1.1 connections are persistent unless the client sends `Connection: close`,
+    /// while HTTP/1.0 connections are only kept open if the client sends `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self
+            .headers
+            .get_all("Connection")
+            .join(",")
+            .to_ascii_lowercase();
+
+        if connection.contains("close") {
+            false
+        } else if connection.contains("keep-alive") {
+            true
+        } else {
+            self.version == "HTTP/1.1"
+        }
+    }
+
     /// # Split To Row
     ///
     /// This function splits a string into rows for every new line
     async fn split_to_row(string: String) -> Vec<String> {
-        let u_strings: Vec<String> = string.split("\r\n").map(|x| x.to_string()).collect();
-
-        let mut strings = Vec::<String>::new();
-        for string in u_strings {
-            if string.contains(";") {
-                let mut sts: Vec<String> = string.split(";").map(|x| x.to_string()).collect();
-                strings.append(&mut sts);
-            } else {
-                strings.push(string.clone());
-            }
-        }
-        strings
+        string.split("\r\n").map(|x| x.to_string()).collect()
     }
 
     /// # Get Method
     ///
-    /// This function gets the method from a http request (Eg, POST)
-    async fn get_method(strings: &Vec<String>) -> Option<HttpMethod> {
-        let mut method: Option<HttpMethod> = None;
-        for string in strings.iter() {
-            for substring in string.split(" ") {
-                match substring {
-                    "GET" => {
-                        method = Some(HttpMethod::Get);
-                        break;
-                    }
-                    "POST" => {
-                        method = Some(HttpMethod::Post);
-                        break;
-                    }
-                    "HEAD" => {
-                        method = Some(HttpMethod::Head);
-                        break;
-                    }
-                    "PUT" => {
-                        method = Some(HttpMethod::Put);
-                        break;
-                    }
-                    "DELETE" => {
-                        method = Some(HttpMethod::Delete);
-                        break;
-                    }
-                    "CONNECT" => {
-                        method = Some(HttpMethod::Connect);
-                        break;
-                    }
-                    "OPTIONS" => {
-                        method = Some(HttpMethod::Options);
-                        break;
-                    }
-                    "TRACE" => {
-                        method = Some(HttpMethod::Trace);
-                        break;
-                    }
-                    _ => continue,
-                }
-            }
-            if !method.is_none() {
-                break;
-            }
+    /// This function gets the method from a http request (Eg, POST). It is the first word of the request line -
+    /// methods we don't know (or anything elsewhere in the request, such as a header) give `None`.
+    async fn get_method(strings: &[String]) -> Option<HttpMethod> {
+        let request_line = strings.first()?;
+        match request_line.split(' ').next()? {
+            "GET" => Some(HttpMethod::Get),
+            "POST" => Some(HttpMethod::Post),
+            "HEAD" => Some(HttpMethod::Head),
+            "PUT" => Some(HttpMethod::Put),
+            "DELETE" => Some(HttpMethod::Delete),
+            "CONNECT" => Some(HttpMethod::Connect),
+            "OPTIONS" => Some(HttpMethod::Options),
+            "TRACE" => Some(HttpMethod::Trace),
+            "PATCH" => Some(HttpMethod::Patch),
+            _ => None,
         }
-
-        method
     }
 
     /// # Get uri
@@ -168,18 +286,28 @@ impl Request {
     /// This funcion gets the URI of the request
     ///
     /// The URI is the requested route (eg, /about)
-    async fn get_uri(strings: &Vec<String>) -> String {
+    async fn get_uri(strings: &[String]) -> String {
         let string = &strings[0];
 
         let strings: Vec<String> = string.split(" ").map(|x| x.to_string()).collect();
 
-        let uri = if strings.len() > 1 {
+        if strings.len() > 1 {
             strings[1].clone()
         } else {
             "/error".to_string()
-        };
+        }
+    }
 
-        uri
+    /// # Get Version
+    ///
+    /// This function gets the HTTP version from the request line (eg, HTTP/1.1)
+    ///
+    /// Requests without a version are treated as HTTP/1.0
+    async fn get_version(strings: &[String]) -> String {
+        match strings[0].split(' ').nth(2) {
+            Some(version) if version.starts_with("HTTP/") => version.trim().to_string(),
+            _ => "HTTP/1.0".to_string(),
+        }
     }
 
     /// # Get Vars
@@ -187,7 +315,7 @@ impl Request {
     /// This function takes in a URI and extracts the GET parameters, returning them as a hashmap
     ///
     /// This can then be used by the callback
-    async fn get_vars(uri: &String) -> (HashMap<String, String>, String) {
+    async fn get_vars(uri: &str) -> (HashMap<String, String>, String) {
         let split_uri: Vec<String> = uri.split("?").map(|x| x.to_string()).collect();
 
         let mut hash_vals = HashMap::<String, String>::new();
@@ -211,129 +339,94 @@ impl Request {
 
     /// # Get post request
     ///
-    /// Takes in the split request, returns a hashmap with the `variables` and `values`.
+    /// Takes in the split request headers and the body, returns a hashmap with the `variables` and `values`.
     ///
-    /// This currently only works for the standard `application/x-www-form-urlencoded` form type,
-    /// and doesn't support `multipart/form-data` yet.
-    async fn get_post_request(strings: &Vec<String>) -> HashMap<String, PostRequest> {
-        //println!("{:?}", strings);
-
-        if !strings.contains(&"Content-Type: application/x-www-form-urlencoded".to_string())
-            && !strings.contains(&"Content-Type: multipart/form-data".to_string())
-        {
-            return HashMap::new();
-        }
-
-        let index_of_post = strings.iter().position(|x| x == "").unwrap();
-
+    /// This works for the standard `application/x-www-form-urlencoded` form type, as well as `multipart/form-data`.
+    /// The data of each value is kept byte for byte as the client sent it.
+    async fn get_post_request(headers: &Headers, body: &[u8]) -> HashMap<String, PostRequest> {
         let mut post_req = HashMap::new();
 
-        if strings.contains(&"Content-Type: application/x-www-form-urlencoded".to_string()) {
-            if strings.len() - 1 > index_of_post {
-                for n in 0..(strings.len() - 1) - index_of_post {
-                    let val = &strings[index_of_post + n + 1];
-                    let args = val.split("&");
-                    for val in args {
-                        let mut split_val = val.split("=");
-                        let p_var = split_val.next().unwrap();
-                        let p_val = split_val.next().unwrap();
-                        let p_r =
-                            PostRequest::new(p_var.to_string(), "".to_string(), p_val.into()).await;
-                        post_req.insert(p_var.to_string(), p_r);
-                    }
-                }
+        // The content type may have parameters, eg `multipart/form-data; boundary=...`
+        let content_type = headers.get("Content-Type").unwrap_or("");
+        let mut params = content_type.split(';').map(|param| param.trim());
+        let media_type = params.next().unwrap_or("");
+
+        if media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            for val in body.split(|byte| *byte == b'&').filter(|val| !val.is_empty()) {
+                let (p_var, p_val) = match val.iter().position(|byte| *byte == b'=') {
+                    Some(position) => (&val[..position], &val[position + 1..]),
+                    None => (val, &val[val.len()..]),
+                };
+                let p_var = String::from_utf8_lossy(p_var).to_string();
+                let p_r = PostRequest::new(p_var.clone(), "".to_string(), p_val.to_vec()).await;
+                post_req.insert(p_var, p_r);
             }
-        } else {
+        } else if media_type.eq_ignore_ascii_case("multipart/form-data") {
             // It is multipart, so extract the data from it and store it in post_req
-
-            let mut boundary = String::new();
-            
-            let mut name = String::new();
-
-            let mut filename = String::new();
-
-            let mut data = Vec::<u8>::new();
-
-            
-
-            for string in strings[index_of_post + 1..].iter() {
-                // Check for the boundaries
-                if string.contains("-----------------------------") {
-                    // Now, we need to extract the boundary hash with regex, eg boundary=---------------------------179249680738152359603687021860
-                    // becomes 179249680738152359603687021860
-                    let re = Regex::new(r"\w*?([\d|\.]+)\w*?([\d{1,4}]+).*").unwrap();
-                    let re_boundary = match re.find(string){
-                        Some(v) => {v.as_str().to_string()},
-                        None => {panic!("Error - multipart form data is invalid!")}
-                    };         
-                    if re_boundary == boundary{
-
-                        // Create a post request based on the data
-                        data.remove(0); // The first item in the data is literally empty bytes, we don't need it.
-                        let p_r = PostRequest::new(name.clone(), filename.clone(), data.clone()).await;
-                        post_req.insert(name.clone(), p_r);
-                        
-                        // We've reached the end of the form data, so reset the data
-                        name.clear();
-                        filename.clear();
-                        data.clear();
-                    }
-                    boundary = re_boundary;
-                    
-                    continue;
-                }
-
-                if !boundary.is_empty(){
-                    // We're in the middle of the form data, so here we extract the data
-                    
-                    // Check for content disposition. Skip if we match
-                    if string.contains("Content-Disposition: form-data"){
-                        continue;
-                    }
-
-                    // Check if there is a filename
-                    if string.contains("filename="){
-                        let re = Regex::new(r#""([^"].+)""#).unwrap();
-                        let re_filename = match re.find(string){
-                            Some(v) => {v.as_str().to_string()},
-                            None => {panic!("Error - multipart form data is invalid!")}
-                        };
-                        // Remove quotes
-                        let re_filename = re_filename.replace("\"", "");
-                        filename = re_filename;
-
-                        continue;
-                    }
-
-                    // Check for the name
-                    if string.contains("name="){
-                        let re = Regex::new(r#""([^"].+)""#).unwrap();
-                        let re_name = match re.find(string){
-                            Some(v) => {v.as_str().to_string()},
-                            None => {panic!("Error - multipart form data is invalid!")}
-                        };
-                        // Remove quotes
-                        let re_name = re_name.replace("\"", "");
-                        name = re_name;
-
-                        continue;
-                    }
-
-                    // Check for content type. If we match, just skip
-                    if string.contains("Content-Type:"){
-                        continue;
-                    }
-
-                    // If we've finished, then we can add the data to the hashmap
-                    data.extend((format!("{}\n", string)).as_bytes());
+            //
+            // The boundary is sent as a parameter of the content type, eg boundary=---------------------------179249680738152359603687021860
+            let boundary = match params.find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                if name.trim().eq_ignore_ascii_case("boundary") {
+                    Some(value.trim())
+                } else {
+                    None
                 }
+            }) {
+                Some(boundary) => boundary.trim_matches('"').to_string(),
+                None => return post_req,
+            };
+
+            // Every part is preceded by a CRLF and `--boundary`. We add a CRLF to the front so the first part is no different.
+            let delimiter = format!("\r\n--{}", boundary).into_bytes();
+            let mut body_with_crlf = b"\r\n".to_vec();
+            body_with_crlf.extend(body);
+
+            let name_re = Regex::new(r#"(?:^|[;\s])name="([^"]*)""#).unwrap();
+            let filename_re = Regex::new(r#"(?:^|[;\s])filename="([^"]*)""#).unwrap();
+
+            let mut rest = match find_bytes(&body_with_crlf, &delimiter) {
+                Some(position) => &body_with_crlf[position + delimiter.len()..],
+                None => return post_req,
+            };
+
+            // The final boundary is followed by `--`
+            while !rest.starts_with(b"--") {
+                let part_end = match find_bytes(rest, &delimiter) {
+                    Some(position) => position,
+                    None => break, // The form data was cut short, so there are no more full parts
+                };
+                let part = &rest[..part_end];
+                rest = &rest[part_end + delimiter.len()..];
+
+                // Each part has its own headers, then the data
+                let part = part.strip_prefix(b"\r\n").unwrap_or(part);
+                let (part_headers, data) = match find_bytes(part, b"\r\n\r\n") {
+                    Some(position) => (&part[..position], &part[position + 4..]),
+                    None => continue,
+                };
+                let part_headers = String::from_utf8_lossy(part_headers);
+
+                let disposition = match part_headers
+                    .split("\r\n")
+                    .find(|line| line.to_ascii_lowercase().starts_with("content-disposition:"))
+                {
+                    Some(disposition) => disposition,
+                    None => continue,
+                };
+
+                let name = match name_re.captures(disposition) {
+                    Some(captures) => captures[1].to_string(),
+                    None => continue,
+                };
+                let filename = filename_re
+                    .captures(disposition)
+                    .map(|captures| captures[1].to_string())
+                    .unwrap_or_default();
+
+                let p_r = PostRequest::new(name.clone(), filename, data.to_vec()).await;
+                post_req.insert(name, p_r);
             }
-
-            // Save the last value in the hashmap, as we've reached the end of the form data. 
-            data.remove(0); // The first item in the data is literally empty bytes, we don't need it.
-            data.remove(data.len() - 1); // We also remove the last value as it's garbage (TODO: FIX THIS)
-            let p_r = PostRequest::new(name.clone(), filename.clone(), data.clone()).await;
-            post_req.insert(name.clone(), p_r);
         }
 
         post_req
@@ -345,29 +438,20 @@ impl Request {
     /// This function gets the user agent from the request
     ///
     /// Can be useful when doing dynamic page downloads (eg, specific downloads for macos or android)
-    async fn get_user_agent(strings: &Vec<String>) -> String {
-        let mut agent = "none".to_string();
-        for string in strings.iter() {
-            if string.contains("User-Agent:") {
-                let agent_raw: Vec<String> = string
-                    .split("User-Agent: ")
-                    .map(|x| x.to_string())
-                    .collect();
-                agent = agent_raw[1].clone();
-            }
-            if agent != "none".to_string() {
-                break;
-            }
-        }
-
-        agent
+    async fn get_user_agent(headers: &Headers) -> String {
+        headers.get("User-Agent").unwrap_or("none").to_string()
     }
 }
 
+/// Find the first position of `needle` in `haystack`
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
 /// # Post Request
 ///
 /// A representation of a post request
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PostRequest {
     pub name: String,
     pub file_name: String,
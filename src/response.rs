@@ -1,43 +1,520 @@
+use crate::headers::is_valid_header_name;
+use crate::{Headers, StatusCode};
+use std::path::PathBuf;
+
 /// # Response
 ///
-/// This enum simplifies return codes for HTTP responses
+/// A HTTP response, made up of a status code, headers and a body.
+///
+/// Responses are built up one step at a time. The `Content-Length` header is set automatically when
+/// the response is turned into bytes, so it doesn't need to be set by hand.
 /// ```
-/// let ok = Response::Ok;
-/// // OR
-/// let ok = Response::from(200);
+/// # use micro_http_async::{Response, StatusCode};
+/// let response = Response::new(StatusCode::CREATED)
+///     .header("Location", "/users/42")
+///     .text("Created user 42");
+///
+/// assert_eq!(response.status().as_u16(), 201);
 /// ```
-pub enum Response {
-    /// All Ok
-    Ok,
-    /// Link/Page has moved, redirect
-    Redirect,
-    /// Client error - something went wrong that was caused by the client
-    ClientErr,
-    /// Server error - something went wrong server side
-    ServerErr,
-}
-
-impl std::convert::From<u32> for Response {
-    /// # from
-    ///
-    /// Convert a u32 to a response
-    fn from(v: u32) -> Response {
-        match v {
-            200..=299 => {
-                return Response::Ok;
-            }
-            300..=399 => {
-                return Response::Redirect;
-            }
-            400..=499 => {
-                return Response::ClientErr;
-            }
-            500..=599 => {
-                return Response::ServerErr;
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// The status code of the response
+    status: StatusCode,
+    /// The response headers
+    headers: Headers,
+    /// The response body
+    body: Body,
+    /// The error this response was built from, so it can be passed on to an error handler
+    error: Option<HttpError>,
+}
+
+/// # Body
+///
+/// The body of a `Response`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Body {
+    /// No body at all
+    Empty,
+    /// A text body, such as HTML or JSON
+    Text(String),
+    /// A binary body, such as an image
+    Bytes(Vec<u8>),
+    /// `len` bytes of a file on disk, starting at `offset`. The file is streamed to the client in blocks,
+    /// rather than being loaded into memory.
+    File {
+        /// The path of the file
+        path: PathBuf,
+        /// Where the body starts in the file
+        offset: u64,
+        /// The number of bytes to send
+        len: u64,
+    },
+    /// A body made of several parts, sent one after the other (eg, the parts of a `multipart/byteranges` response)
+    Parts(Vec<Body>),
+}
+
+impl Body {
+    /// # Len
+    ///
+    /// The length of the body in bytes
+    pub fn len(&self) -> usize {
+        match self {
+            Body::Empty => 0,
+            Body::Text(text) => text.len(),
+            Body::Bytes(bytes) => bytes.len(),
+            Body::File { len, .. } => *len as usize,
+            Body::Parts(parts) => parts.iter().map(Body::len).sum(),
+        }
+    }
+
+    /// # Is Empty
+    ///
+    /// Check whether the body has no content
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// # Pieces
+    ///
+    /// The bodies this body is made of, in order, with any `Parts` flattened out
+    pub(crate) fn pieces(&self) -> Vec<&Body> {
+        match self {
+            Body::Parts(parts) => parts.iter().flat_map(Body::pieces).collect(),
+            body => vec![body],
+        }
+    }
+
+    /// # Is Streamed
+    ///
+    /// Check whether any of the body comes from a file, which is streamed rather than held in memory
+    pub(crate) fn is_streamed(&self) -> bool {
+        self.pieces().iter().any(|piece| matches!(piece, Body::File { .. }))
+    }
+}
+
+impl Response {
+    /// # New
+    ///
+    /// Create a new response with the given status code, and no headers or body
+    pub fn new(status: impl Into<StatusCode>) -> Self {
+        Self {
+            status: status.into(),
+            headers: Headers::new(),
+            body: Body::Empty,
+            error: None,
+        }
+    }
+
+    /// # Ok
+    ///
+    /// Create a new `200 OK` response
+    pub fn ok() -> Self {
+        Self::new(StatusCode::OK)
+    }
+
+    /// # Redirect
+    ///
+    /// Create a `302 Found` response which sends the client to `location`. Line breaks in `location` are
+    /// removed when the response is sent (see [header](#method.header)).
+    pub fn redirect(location: &str) -> Self {
+        Self::new(StatusCode::FOUND).header("Location", location)
+    }
+
+    /// # Header
+    ///
+    /// Add a header to the response. If the header was already set, both values are sent.
+    ///
+    /// Headers can't be used to inject more headers (or a body) into the response - when it is sent, CR, LF and NUL
+    /// are removed from values, and headers with an invalid name are left out.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// # Text
+    ///
+    /// Set the body to plain text. Sets the content type to `text/plain` if it isn't set already.
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.with_body(Body::Text(text.into()), "text/plain; charset=utf-8")
+    }
+
+    /// # Html
+    ///
+    /// Set the body to HTML. Sets the content type to `text/html` if it isn't set already.
+    pub fn html(self, html: impl Into<String>) -> Self {
+        self.with_body(Body::Text(html.into()), "text/html; charset=utf-8")
+    }
+
+    /// # Json
+    ///
+    /// Serialize `value` to JSON, and set it as the body. Sets the content type to `application/json`
+    /// if it isn't set already.
+    ///
+    /// If the value can't be serialized, the response is turned into a `500 Internal Server Error`.
+    pub fn json<T: serde::Serialize + ?Sized>(self, value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(json) => self.with_body(Body::Text(json), "application/json"),
+            Err(e) => Response::new(StatusCode::INTERNAL_SERVER_ERROR).text(e.to_string()),
+        }
+    }
+
+    /// # Bytes
+    ///
+    /// Set the body to binary data. Sets the content type to `application/octet-stream` if it isn't set already.
+    pub fn bytes(self, bytes: impl Into<Vec<u8>>) -> Self {
+        self.with_body(Body::Bytes(bytes.into()), "application/octet-stream")
+    }
+
+    /// # File
+    ///
+    /// Set the body to the first `len` bytes of a file, which is streamed from disk when the response is sent.
+    /// Sets the content type to `application/octet-stream` if it isn't set already.
+    pub fn file(self, path: impl Into<PathBuf>, len: u64) -> Self {
+        self.file_range(path, 0, len)
+    }
+
+    /// # File Range
+    ///
+    /// Set the body to `len` bytes of a file, starting at `offset`, which are streamed from disk when the response
+    /// is sent. Sets the content type to `application/octet-stream` if it isn't set already.
+    pub fn file_range(self, path: impl Into<PathBuf>, offset: u64, len: u64) -> Self {
+        let body = Body::File {
+            path: path.into(),
+            offset,
+            len,
+        };
+        self.with_body(body, "application/octet-stream")
+    }
+
+    /// # Status
+    ///
+    /// Get the status code of the response
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// # Set Status
+    ///
+    /// Change the status code of the response
+    pub fn set_status(&mut self, status: impl Into<StatusCode>) {
+        self.status = status.into();
+    }
+
+    /// # Set Body
+    ///
+    /// Replace the body of the response. Unlike the builder methods, this leaves the headers alone.
+    pub fn set_body(&mut self, body: Body) {
+        self.body = body;
+    }
+
+    /// # Headers
+    ///
+    /// Get the headers of the response
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// # Headers Mut
+    ///
+    /// Get the headers of the response, so they can be changed
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    /// # Body
+    ///
+    /// Get the body of the response
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    /// # Take Error
+    ///
+    /// Take the `HttpError` this response was built from, if it was built from one
+    pub(crate) fn take_error(&mut self) -> Option<HttpError> {
+        self.error.take()
+    }
+
+    /// # Into Bytes
+    ///
+    /// Turn the response into the bytes sent to the client - the status line, headers and body.
+    ///
+    /// `Content-Length` is added automatically (unless the response already has a `Content-Length` or
+    /// `Transfer-Encoding`), and the body is left off for status codes which can't have one, such as 204 and 304.
+    ///
+    /// File bodies are read into memory. The server streams them instead.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.serialize(true)
+    }
+
+    /// # Serialize
+    ///
+    /// Turn the response into bytes. Responses to HEAD requests have the same headers as they would for a
+    /// GET request (including `Content-Length`), but leave off the body.
+    pub(crate) fn serialize(self, include_body: bool) -> Vec<u8> {
+        let mut bytes = self.serialize_head();
+        if self.status.allows_body() && include_body {
+            for piece in self.body.pieces() {
+                match piece {
+                    Body::Text(text) => bytes.extend(text.as_bytes()),
+                    Body::Bytes(body) => bytes.extend(body),
+                    Body::File { path, offset, len } => match read_file(path, *offset, *len) {
+                        Ok(body) => bytes.extend(body),
+                        Err(e) => return HttpError::from(e).into_response().serialize(include_body),
+                    },
+                    Body::Empty | Body::Parts(_) => {}
+                }
             }
-            _ => {
-                return Response::ServerErr;
+        }
+
+        bytes
+    }
+
+    /// # Serialize Head
+    ///
+    /// Turn the status line and headers into bytes, including the automatic `Content-Length`
+    pub(crate) fn serialize_head(&self) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+
+        for (name, value) in self.headers.iter() {
+            // A name which could break out of the header line can't be made safe, so leave the header out
+            if !is_valid_header_name(name) {
+                continue;
             }
+            head.push_str(&format!("{}: {}\r\n", name, sanitize_header_value(value)));
+        }
+
+        if self.status.allows_body() && !self.headers.contains("Content-Length") && !self.headers.contains("Transfer-Encoding") {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+
+        head.into_bytes()
+    }
+
+    /// Set the body, and the content type if it hasn't been set already
+    fn with_body(mut self, body: Body, content_type: &str) -> Self {
+        if !self.headers.contains("Content-Type") {
+            self.headers.insert("Content-Type", content_type);
         }
+        self.body = body;
+        self
+    }
+}
+
+/// Remove the characters which would end the header line (or the head) early, so a value can't add headers
+fn sanitize_header_value(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.contains(['\r', '\n', '\0']) {
+        value.replace(['\r', '\n', '\0'], "").into()
+    } else {
+        value.into()
+    }
+}
+
+/// Read `len` bytes of a file, starting at `offset`
+fn read_file(path: &std::path::Path, offset: u64, len: u64) -> std::io::Result<Vec<u8>> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut body = Vec::with_capacity(len as usize);
+    file.take(len).read_to_end(&mut body)?;
+    if (body.len() as u64) < len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(body)
+}
+
+/// # Into Response
+///
+/// Anything a route handler can return. Handlers can return a full `Response`, or something simpler
+/// which is turned into one:
+///
+/// - `String` and `&'static str` become a `200 OK` plain text response
+/// - `Vec<u8>` becomes a `200 OK` binary response
+/// - `serde_json::Value` becomes a `200 OK` JSON response
+/// - `StatusCode` becomes an empty response with that status
+/// - `(StatusCode, T)` changes the status of the response `T` is turned into
+/// - `Result<T, E>` returns `T` as a response, or turns the error into an error response (see [HttpError](struct.HttpError.html))
+///
+/// **Example**
+/// ```
+/// # use micro_http_async::{Request, Route, StatusCode};
+/// async fn handler(request: Request) -> Result<(StatusCode, String), String> {
+///     let name = request.get_request.get("name").ok_or("No name given")?;
+///     Ok((StatusCode::CREATED, format!("Created {}", name)))
+/// }
+///
+/// let route = Route::new(Box::new(handler));
+/// # drop(route);
+/// ```
+pub trait IntoResponse {
+    /// Turn the value into a `Response`
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        Response::ok().text(self)
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response {
+        Response::ok().text(self)
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        Response::ok().bytes(self)
+    }
+}
+
+impl IntoResponse for serde_json::Value {
+    fn into_response(self) -> Response {
+        Response::ok().json(&self)
+    }
+}
+
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> Response {
+        Response::new(self)
+    }
+}
+
+impl IntoResponse for () {
+    fn into_response(self) -> Response {
+        Response::new(StatusCode::NO_CONTENT)
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    fn into_response(self) -> Response {
+        let (status, response) = self;
+        let mut response = response.into_response();
+        response.set_status(status);
+        response
+    }
+}
+
+impl<T: IntoResponse, E: Into<HttpError>> IntoResponse for Result<T, E> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(response) => response.into_response(),
+            Err(e) => e.into().into_response(),
+        }
+    }
+}
+
+/// # Http Error
+///
+/// An error which is sent to the client as an error response, made up of a status code and a message.
+///
+/// Handlers which return a `Result` can use any error which converts into a `HttpError`. Plain messages
+/// (`String` and `&str`) become a `500 Internal Server Error`, while `(StatusCode, message)` picks the status.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpError {
+    /// The status code of the error response
+    pub status: StatusCode,
+    /// A message describing what went wrong
+    pub message: String,
+}
+
+impl HttpError {
+    /// # New
+    ///
+    /// Create a new error with a status code and a message
+    pub fn new(status: impl Into<StatusCode>, message: impl Into<String>) -> Self {
+        Self {
+            status: status.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response {
+        // Don't send an empty page - fall back to the reason phrase
+        let message = if self.message.is_empty() {
+            self.status.reason().to_string()
+        } else {
+            self.message.clone()
+        };
+        let mut response = Response::new(self.status).text(message);
+        response.error = Some(self);
+        response
+    }
+}
+
+impl From<String> for HttpError {
+    fn from(message: String) -> Self {
+        HttpError::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+impl From<&str> for HttpError {
+    fn from(message: &str) -> Self {
+        HttpError::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+impl From<StatusCode> for HttpError {
+    fn from(status: StatusCode) -> Self {
+        HttpError::new(status, status.reason())
+    }
+}
+
+impl<M: Into<String>> From<(StatusCode, M)> for HttpError {
+    fn from((status, message): (StatusCode, M)) -> Self {
+        HttpError::new(status, message)
+    }
+}
+
+impl From<std::io::Error> for HttpError {
+    fn from(e: std::io::Error) -> Self {
+        let status = match e.kind() {
+            std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            std::io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        HttpError::new(status, e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head(response: Response) -> String {
+        String::from_utf8(response.serialize_head()).unwrap()
+    }
+
+    #[test]
+    fn strips_line_breaks_from_header_values() {
+        let head = head(Response::redirect("/u/x\r\nSet-Cookie: evil=1"));
+        assert!(head.contains("Location: /u/xSet-Cookie: evil=1\r\n"));
+        assert!(!head.contains("\r\nSet-Cookie"));
+    }
+
+    #[test]
+    fn leaves_out_invalid_header_names() {
+        let head = head(Response::ok().header("X-Ok", "1").header("Bad\r\nSet-Cookie", "evil=1").header("", "x").header("A B", "x"));
+        assert_eq!(head, "HTTP/1.1 200 OK\r\nX-Ok: 1\r\nContent-Length: 0\r\n\r\n");
     }
 }
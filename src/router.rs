@@ -0,0 +1,363 @@
+use crate::path::{percent_decode, PathPattern, Segment};
+use crate::HttpMethod;
+
+/// # Route Error
+///
+/// The reasons a route can't be added to `Routes`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    /// The route has a parameter or wildcard where another route already has one with a different name,
+    /// eg `/a/:y` when `/a/:x` exists. Both would match the same requests.
+    Conflict {
+        /// The route being added
+        route: String,
+        /// The name the existing route gave the parameter or wildcard
+        existing: String,
+    },
+    /// The route is already registered for the method (`None` meaning the route which accepts any method)
+    Duplicate {
+        /// The route being added
+        route: String,
+        /// The method the route is already registered for
+        method: Option<HttpMethod>,
+    },
+    /// The route path can't be parsed, eg `/files/*path/edit`, where a wildcard (which takes the rest of the path)
+    /// is followed by another segment, or `/users/:`, where a parameter has no name
+    InvalidPattern {
+        /// The route being added
+        route: String,
+        /// What is wrong with it
+        reason: &'static str,
+    },
+}
+
+impl std::fmt::Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteError::Conflict { route, existing } => write!(
+                f,
+                "route '{}' conflicts with '{}', which is already registered on the same segment",
+                route, existing
+            ),
+            RouteError::Duplicate { route, method: Some(method) } => {
+                write!(f, "route '{}' is already registered for {}", route, method)
+            }
+            RouteError::Duplicate { route, method: None } => {
+                write!(f, "route '{}' is already registered for any method", route)
+            }
+            RouteError::InvalidPattern { route, reason } => write!(f, "route '{}' is invalid: {}", route, reason),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+/// # Token
+///
+/// A part of a route path, as it is stored in the tree. Consecutive static segments are joined together
+/// (including their slashes), so that they can be compressed into as few nodes as possible.
+enum Token {
+    /// Text which must match exactly, eg `/users/`
+    Static(String),
+    /// A parameter, which matches up to the next `/`
+    Param(String),
+    /// A wildcard, which matches the rest of the path
+    Wildcard(String),
+}
+
+/// Turn a route path into the tokens stored in the tree.
+///
+/// `/users/:id/posts` becomes `/users/`, `:id`, `/posts`. Wildcards keep their slash out of the static text,
+/// so that `/files/*path` also matches `/files`.
+fn tokenize(path: &str) -> Result<Vec<Token>, RouteError> {
+    let pattern = PathPattern::parse(path)?;
+    if pattern.segments.is_empty() {
+        return Ok(vec![Token::Static("/".to_string())]);
+    }
+
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    for segment in pattern.segments {
+        match segment {
+            Segment::Static(segment) => {
+                text.push('/');
+                text.push_str(&segment);
+            }
+            Segment::Param(name) => {
+                text.push('/');
+                tokens.push(Token::Static(std::mem::take(&mut text)));
+                tokens.push(Token::Param(name));
+            }
+            Segment::Wildcard(name) => {
+                tokens.push(Token::Static(std::mem::take(&mut text)));
+                tokens.push(Token::Wildcard(name));
+            }
+        }
+    }
+    tokens.push(Token::Static(text));
+
+    Ok(tokens)
+}
+
+/// Put a request path in the form the tree stores paths in - empty segments and trailing slashes are removed
+fn normalise(path: &str) -> String {
+    let segments: Vec<&str> = crate::path::split_path(path).collect();
+    format!("/{}", segments.join("/"))
+}
+
+/// The length of the common prefix of two strings, in bytes
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, a), b)| a != b)
+        .map(|((index, _), _)| index)
+        .unwrap_or_else(|| a.len().min(b.len()))
+}
+
+/// # Node
+///
+/// A node of the compressed prefix (radix) tree used to look up routes.
+///
+/// Each node matches a piece of static text. Below it are the nodes for static text which can follow it
+/// (no two of which start with the same character), a parameter which can follow it, and a wildcard which
+/// can follow it. Looking up a path only walks down the branches which match it, so it takes time
+/// proportional to the length of the path, no matter how many routes there are.
+pub(crate) struct Node<T> {
+    /// The static text this node matches
+    prefix: String,
+    /// The value stored for paths ending at this node
+    value: Option<T>,
+    /// Nodes for the static text which can follow this node
+    children: Vec<Node<T>>,
+    /// The parameter which can follow this node, and the node for whatever follows the parameter
+    param: Option<Box<(String, Node<T>)>>,
+    /// The wildcard which can follow this node, and the value stored for it
+    wildcard: Option<(String, Option<T>)>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Self::new(String::new())
+    }
+}
+
+impl<T> Node<T> {
+    /// # New
+    ///
+    /// Create an empty node which matches `prefix`
+    fn new(prefix: String) -> Self {
+        Self {
+            prefix,
+            value: None,
+            children: Vec::new(),
+            param: None,
+            wildcard: None,
+        }
+    }
+
+    /// # Insert
+    ///
+    /// Find the slot the value for a route path is stored in, creating the nodes it needs.
+    ///
+    /// Fails if the path can't be parsed, or gives a parameter or wildcard a different name than an existing route does.
+    pub(crate) fn insert(&mut self, path: &str) -> Result<&mut Option<T>, RouteError> {
+        let mut node = self;
+        for token in tokenize(path)? {
+            match token {
+                Token::Static(text) => {
+                    node = node.insert_static(&text);
+                }
+                Token::Param(name) => {
+                    let param = node.param.get_or_insert_with(|| Box::new((name.clone(), Node::default())));
+                    if param.0 != name {
+                        return Err(RouteError::Conflict {
+                            route: path.to_string(),
+                            existing: format!(":{}", param.0),
+                        });
+                    }
+                    node = &mut param.1;
+                }
+                Token::Wildcard(name) => {
+                    let wildcard = node.wildcard.get_or_insert_with(|| (name.clone(), None));
+                    if wildcard.0 != name {
+                        return Err(RouteError::Conflict {
+                            route: path.to_string(),
+                            existing: format!("*{}", wildcard.0),
+                        });
+                    }
+                    // Nothing can follow a wildcard
+                    return Ok(&mut wildcard.1);
+                }
+            }
+        }
+
+        Ok(&mut node.value)
+    }
+
+    /// # Insert Static
+    ///
+    /// Find the node which ends after `text` (relative to this node), splitting nodes which only share
+    /// part of their prefix with it.
+    fn insert_static(&mut self, text: &str) -> &mut Node<T> {
+        if text.is_empty() {
+            return self;
+        }
+
+        let index = match self.children.iter().position(|child| child.prefix.chars().next() == text.chars().next()) {
+            Some(index) => index,
+            None => {
+                self.children.push(Node::new(text.to_string()));
+                return self.children.last_mut().unwrap();
+            }
+        };
+
+        let child = &mut self.children[index];
+        let common = common_prefix(&child.prefix, text);
+
+        // The child only shares part of its prefix with the text, so split it in two
+        if common < child.prefix.len() {
+            let suffix = child.prefix.split_off(common);
+            let mut lower = Node::new(suffix);
+            lower.value = child.value.take();
+            lower.children = std::mem::take(&mut child.children);
+            lower.param = child.param.take();
+            lower.wildcard = child.wildcard.take();
+            child.children.push(lower);
+        }
+
+        child.insert_static(&text[common..])
+    }
+
+    /// # Find
+    ///
+    /// Find the value stored for the route which matches `path`, along with the parameters captured from it.
+    ///
+    /// Static text is preferred over parameters, which are preferred over wildcards - if a more specific branch
+    /// doesn't lead to a match, the next one is tried.
+    pub(crate) fn find(&self, path: &str) -> Option<(&T, Vec<(String, String)>)> {
+        let path = normalise(path);
+        let mut params = Vec::new();
+        let value = self.find_from(&path, &mut params)?;
+
+        // Parameters are collected as the search unwinds, so they come out backwards
+        params.reverse();
+        Some((value, params))
+    }
+
+    /// Search the subtree below this node (whose prefix has already been matched) for `rest` of the path
+    fn find_from<'a>(&'a self, rest: &str, params: &mut Vec<(String, String)>) -> Option<&'a T> {
+        if rest.is_empty() {
+            if let Some(value) = self.value.as_ref() {
+                return Some(value);
+            }
+        }
+
+        // Static text first
+        for child in self.children.iter() {
+            if let Some(after) = rest.strip_prefix(child.prefix.as_str()) {
+                if let Some(value) = child.find_from(after, params) {
+                    return Some(value);
+                }
+            }
+        }
+
+        // Then a parameter, which takes everything up to the next slash
+        if let Some(param) = self.param.as_ref() {
+            let end = rest.find('/').unwrap_or(rest.len());
+            if end > 0 {
+                if let Some(value) = param.1.find_from(&rest[end..], params) {
+                    params.push((param.0.clone(), percent_decode(&rest[..end])));
+                    return Some(value);
+                }
+            }
+        }
+
+        // Then a wildcard, which takes the rest of the path
+        if let Some((name, Some(value))) = self.wildcard.as_ref() {
+            if rest.is_empty() || rest.starts_with('/') {
+                params.push((name.clone(), percent_decode(rest.trim_start_matches('/'))));
+                return Some(value);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a tree with each route storing its own path
+    fn tree(routes: &[&'static str]) -> Node<&'static str> {
+        let mut tree = Node::default();
+        for route in routes {
+            *tree.insert(route).unwrap() = Some(*route);
+        }
+        tree
+    }
+
+    fn find(tree: &Node<&'static str>, path: &str) -> Option<(&'static str, Vec<(String, String)>)> {
+        tree.find(path).map(|(route, params)| (*route, params))
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn finds_static_routes() {
+        let tree = tree(&["/", "/users", "/users/all", "/user"]);
+        assert_eq!(find(&tree, "/"), Some(("/", params(&[]))));
+        assert_eq!(find(&tree, "/users/"), Some(("/users", params(&[]))));
+        assert_eq!(find(&tree, "//users//all"), Some(("/users/all", params(&[]))));
+        assert_eq!(find(&tree, "/user"), Some(("/user", params(&[]))));
+        assert_eq!(find(&tree, "/use"), None);
+        assert_eq!(find(&tree, "/users/none"), None);
+    }
+
+    #[test]
+    fn prefers_static_then_params_then_wildcards() {
+        let tree = tree(&["/a/new", "/a/:id", "/a/*rest"]);
+        assert_eq!(find(&tree, "/a/new"), Some(("/a/new", params(&[]))));
+        assert_eq!(find(&tree, "/a/42"), Some(("/a/:id", params(&[("id", "42")]))));
+        assert_eq!(find(&tree, "/a/42/b"), Some(("/a/*rest", params(&[("rest", "42/b")]))));
+        assert_eq!(find(&tree, "/a"), Some(("/a/*rest", params(&[("rest", "")]))));
+    }
+
+    #[test]
+    fn backtracks_when_a_more_specific_branch_fails() {
+        let tree = tree(&["/a/b/d", "/a/:x/c", "/files/*path"]);
+        assert_eq!(find(&tree, "/a/b/c"), Some(("/a/:x/c", params(&[("x", "b")]))));
+        assert_eq!(find(&tree, "/a/b/d"), Some(("/a/b/d", params(&[]))));
+        assert_eq!(find(&tree, "/files2"), None);
+        assert_eq!(find(&tree, "/files/css/site.css"), Some(("/files/*path", params(&[("path", "css/site.css")]))));
+    }
+
+    #[test]
+    fn decodes_params() {
+        let tree = tree(&["/users/:name/posts/:post"]);
+        assert_eq!(
+            find(&tree, "/users/j%20doe/posts/7"),
+            Some(("/users/:name/posts/:post", params(&[("name", "j doe"), ("post", "7")])))
+        );
+    }
+
+    #[test]
+    fn reports_conflicting_names() {
+        let mut tree = tree(&["/a/:x", "/f/*p"]);
+        assert!(matches!(tree.insert("/a/:y"), Err(RouteError::Conflict { .. })));
+        assert!(matches!(tree.insert("/f/*q"), Err(RouteError::Conflict { .. })));
+        assert!(tree.insert("/a/:x/b").is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let mut tree = Node::<&str>::default();
+        for route in ["/f/*p/edit", "/f/*p/*q", "/users/:", "/files/*"] {
+            assert!(matches!(tree.insert(route), Err(RouteError::InvalidPattern { .. })), "{}", route);
+        }
+        // Nothing was added, so the route the user meant can still be registered
+        assert!(tree.insert("/f/*p").unwrap().is_none());
+    }
+}
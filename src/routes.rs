@@ -1,10 +1,10 @@
-use crate::Request;
-use chunked_transfer::Encoder;
+use crate::router::{Node, RouteError};
+use crate::{HttpError, HttpMethod, IntoResponse, Request, Response, StaticFiles, StatusCode};
 use futures::future::BoxFuture;
-use std::collections::HashMap;
+use futures::FutureExt;
+use std::collections::{BTreeMap, HashMap};
 use std::future::Future;
-use std::io::Write;
-use tokio::io::AsyncReadExt;
+use std::panic::AssertUnwindSafe;
 
 /// # RouteDef
 ///
@@ -12,19 +12,27 @@ use tokio::io::AsyncReadExt;
 ///
 /// This cleans up the API quite a bit, only requiring the user to Box the function they want to use.
 ///
+/// The function can return anything which implements [IntoResponse](trait.IntoResponse.html) - a `Response`, a `String`,
+/// a status and a body, or a `Result` whose error is turned into an error response.
+///
 /// Hopefully I figure out macros soon so I can simplify the whole process further to a single macro.
-pub trait RouteDef {
-    fn call(&self, request: Request) -> BoxFuture<'static, Result<String, String>>;
+///
+/// Routes are shared between every connection task, so the function must be `Send + Sync`.
+pub trait RouteDef: Send + Sync {
+    fn call(&self, request: Request) -> BoxFuture<'static, Response>;
 }
-impl<T, F> RouteDef for T
+impl<T, F, R> RouteDef for T
 where
-    T: Fn(Request) -> F,
-    F: Future<Output = Result<String, String>> + Send + 'static,
+    T: Fn(Request) -> F + Send + Sync,
+    F: Future<Output = R> + Send + 'static,
+    R: IntoResponse,
 {
     /// # Call
-    /// Run the function (defined as being a future of type T), taking in the `request` we want to use
-    fn call(&self, request: Request) -> BoxFuture<'static, Result<String, String>> {
-        Box::pin(self(request))
+    /// Run the function (defined as being a future of type T), taking in the `request` we want to use,
+    /// and turn whatever it returns into a `Response`
+    fn call(&self, request: Request) -> BoxFuture<'static, Response> {
+        let future = self(request);
+        Box::pin(async move { future.await.into_response() })
     }
 }
 
@@ -48,41 +56,134 @@ impl Route {
 
     /// # Run
     ///
-    /// Run the function, taking in the request as its input. It will return a corresponding DataType based on the output of the function.
-    pub async fn run(&self, request: Request) -> DataType {
-        // Check that our function returned an Ok result, and unwrap it after it executes
-        if let Ok(v) = self.function.call(request).await {
-            DataType::Text(v)
-        } else {
-            DataType::Text(String::new()) // Err returned, just return nothing
-        }
+    /// Run the function, taking in the request as its input. It will return the `Response` the function produced.
+    pub async fn run(&self, request: Request) -> Response {
+        self.function.call(request).await
     }
 }
 
-/// # DataType
+/// # ErrorRouteDef
 ///
-/// This returns the data type of the response, wrapping the response as well
+/// The error handler version of [RouteDef](trait.RouteDef.html). Error handlers take in the original `Request`,
+/// and the `HttpError` which caused the error response, so they can explain what went wrong.
+pub trait ErrorRouteDef: Send + Sync {
+    fn call(&self, request: Request, error: HttpError) -> BoxFuture<'static, Response>;
+}
+impl<T, F, R> ErrorRouteDef for T
+where
+    T: Fn(Request, HttpError) -> F + Send + Sync,
+    F: Future<Output = R> + Send + 'static,
+    R: IntoResponse,
+{
+    /// # Call
+    /// Run the function, taking in the `request` and the `error`, and turn whatever it returns into a `Response`
+    fn call(&self, request: Request, error: HttpError) -> BoxFuture<'static, Response> {
+        let future = self(request, error);
+        Box::pin(async move { future.await.into_response() })
+    }
+}
+
+/// # Error Route
 ///
-/// Used mostly for returning static images as bytes
+/// This struct defines an error handler, which builds the response sent for an error status (such as 404 or 500).
 ///
-/// for example, if you're requesting for a static image from say `/static/img.png`,
-/// you would want `Bytes(content)` instead of `Text(content)`. The API already handles
-/// this for you, but it is worth keeping in mind how it works behind the scenes
-pub enum DataType {
-    /// Defines a Text data type - this is for returning text, such as HTML
-    Text(String),
-    /// Defines a Bytes data type - this is for returning binary data, such as images
-    Bytes(Vec<u8>),
+/// See [Routes::set_error_handler](struct.Routes.html#method.set_error_handler).
+pub struct ErrorRoute {
+    /// The async callback function, boxed so that it can live on the heap
+    function: Box<dyn ErrorRouteDef>,
+}
+
+impl ErrorRoute {
+    /// # New
+    ///
+    /// Create a new error route, taking in a Boxed function as its input.
+    pub fn new(function: Box<dyn ErrorRouteDef>) -> Self {
+        Self { function }
+    }
+
+    /// # Run
+    ///
+    /// Run the function, taking in the request and the error which occurred.
+    pub async fn run(&self, request: Request, error: HttpError) -> Response {
+        self.function.call(request, error).await
+    }
+}
+
+/// # Method Routes
+///
+/// The routes registered on a single path - one per method, plus an optional route which accepts any method
+#[derive(Default)]
+struct MethodRoutes {
+    /// Routes which only accept a single method
+    methods: BTreeMap<HttpMethod, Route>,
+    /// The route used for methods without their own route
+    any: Option<Route>,
+}
+
+impl MethodRoutes {
+    /// # Find
+    ///
+    /// Find the route which handles `method`. HEAD requests use the GET route if there is no HEAD route.
+    fn find(&self, method: HttpMethod) -> Option<&Route> {
+        self.methods
+            .get(&method)
+            .or_else(|| match method {
+                HttpMethod::Head => self.methods.get(&HttpMethod::Get),
+                _ => None,
+            })
+            .or(self.any.as_ref())
+    }
+
+    /// # Allow
+    ///
+    /// The value of the `Allow` header for this path - every method which has a route
+    fn allow(&self) -> String {
+        let mut methods: Vec<HttpMethod> = self.methods.keys().copied().collect();
+        if self.methods.contains_key(&HttpMethod::Get) {
+            methods.push(HttpMethod::Head);
+        }
+        methods.push(HttpMethod::Options);
+        methods.sort();
+        methods.dedup();
+
+        methods.iter().map(|method| method.as_str()).collect::<Vec<&str>>().join(", ")
+    }
 }
 
 /// # Routes
 ///
-/// This struct defines the routes. It uses a hashmap to do this.
+/// This struct defines the routes. It uses a compressed prefix (radix) tree to do this, so finding the route
+/// for a request takes time proportional to the length of its path, no matter how many routes there are.
+///
+/// Paths can capture parts of the request path - `/users/:id` matches `/users/42`, and `/files/*path` matches
+/// `/files/css/site.css`. The captured values are available through [Request::param](struct.Request.html#method.param).
+/// Trailing slashes are ignored, so `/users/42/` matches as well. When more than one path matches, static segments
+/// are preferred over parameters, and parameters are preferred over wildcards, going from the start of the path.
+///
+/// Each path can have a route per `HttpMethod` (see [get](#method.get), [post](#method.post) and friends), and
+/// a route which accepts any method ([any](#method.any)). Requests using a method the path has no route for are
+/// answered with `405 Method Not Allowed` and an `Allow` header, and OPTIONS requests are answered automatically.
+///
+/// Directories of static files can be served with [mount](#method.mount). Requests which don't match a route,
+/// but are under the prefix of a `StaticFiles` mount, are answered with the file they ask for.
 ///
-/// `HashMap<Route, Content>` where content is the return content (ie, html or json).
+/// Requests which don't match any route are passed to the fallback route (see [set_fallback](#method.set_fallback)),
+/// or answered with `404 Not Found` if there isn't one. Error responses (404, 405, 413, 500 and so on) can be
+/// customised with [set_error_handler](#method.set_error_handler).
+///
+/// Adding a route which conflicts with an existing one (eg, `/a/:y` when `/a/:x` exists, or the same path and method twice)
+/// returns a [RouteError](enum.RouteError.html), rather than replacing the existing route. So does a path which can't
+/// be parsed, such as `/files/*path/edit` (a wildcard must be the last segment).
+#[derive(Default)]
 pub struct Routes {
-    /// The hashmap of routes. This stores the route (ie, `/`) and the content (a valid Route, which holds the callback function)
-    routes: HashMap<String, Route>,
+    /// The tree of routes. This stores the route (ie, `/`) and the content (the Routes for each method, which hold the callback functions)
+    tree: Node<MethodRoutes>,
+    /// The static file mounts, with the longest prefix first
+    static_files: Vec<StaticFiles>,
+    /// The fallback route, used when no route matches
+    fallback: Option<Route>,
+    /// The error handlers for each error status
+    error_handlers: HashMap<StatusCode, ErrorRoute>,
 }
 
 impl Routes {
@@ -90,101 +191,230 @@ impl Routes {
     ///
     /// Create a new `Route` struct
     pub async fn new() -> Self {
-        Self {
-            routes: HashMap::<String, Route>::new(),
-        }
+        Self::default()
     }
 
     /// # Add Route
     ///
-    /// Adds a new route to the routes hashmap. If the route already exists,
-    /// its value is updated
-    pub async fn add_route(&mut self, route: String, content: Route) {
-        self.routes.insert(route, content);
+    /// Adds a new route to the routes tree, which accepts any method.
+    ///
+    /// The route `err` is special - it sets the fallback route (see [set_fallback](#method.set_fallback)).
+    pub async fn add_route(&mut self, route: String, content: Route) -> Result<(), RouteError> {
+        self.any(route, content).await
+    }
+
+    /// # Add Method Route
+    ///
+    /// Adds a new route to the routes tree, which only accepts `method`
+    pub async fn add_method_route(&mut self, method: HttpMethod, route: impl Into<String>, content: Route) -> Result<(), RouteError> {
+        let route = route.into();
+        let method_routes = self.path_routes(&route)?;
+        if method_routes.methods.contains_key(&method) {
+            return Err(RouteError::Duplicate { route, method: Some(method) });
+        }
+        method_routes.methods.insert(method, content);
+
+        Ok(())
+    }
+
+    /// # Get
+    ///
+    /// Adds a route which handles GET (and HEAD) requests
+    pub async fn get(&mut self, route: impl Into<String>, content: Route) -> Result<(), RouteError> {
+        self.add_method_route(HttpMethod::Get, route, content).await
+    }
+
+    /// # Post
+    ///
+    /// Adds a route which handles POST requests
+    pub async fn post(&mut self, route: impl Into<String>, content: Route) -> Result<(), RouteError> {
+        self.add_method_route(HttpMethod::Post, route, content).await
+    }
+
+    /// # Put
+    ///
+    /// Adds a route which handles PUT requests
+    pub async fn put(&mut self, route: impl Into<String>, content: Route) -> Result<(), RouteError> {
+        self.add_method_route(HttpMethod::Put, route, content).await
+    }
+
+    /// # Delete
+    ///
+    /// Adds a route which handles DELETE requests
+    pub async fn delete(&mut self, route: impl Into<String>, content: Route) -> Result<(), RouteError> {
+        self.add_method_route(HttpMethod::Delete, route, content).await
+    }
+
+    /// # Patch
+    ///
+    /// Adds a route which handles PATCH requests
+    pub async fn patch(&mut self, route: impl Into<String>, content: Route) -> Result<(), RouteError> {
+        self.add_method_route(HttpMethod::Patch, route, content).await
+    }
+
+    /// # Any
+    ///
+    /// Adds a route which handles every method that doesn't have its own route on the same path
+    pub async fn any(&mut self, route: impl Into<String>, content: Route) -> Result<(), RouteError> {
+        let route = route.into();
+        if route == "err" {
+            self.set_fallback(content).await;
+            return Ok(());
+        }
+
+        let method_routes = self.path_routes(&route)?;
+        if method_routes.any.is_some() {
+            return Err(RouteError::Duplicate { route, method: None });
+        }
+        method_routes.any = Some(content);
+
+        Ok(())
+    }
+
+    /// # Path Routes
+    ///
+    /// Get the routes registered on a path, creating them if there aren't any yet
+    fn path_routes(&mut self, route: &str) -> Result<&mut MethodRoutes, RouteError> {
+        Ok(self.tree.insert(route)?.get_or_insert_with(MethodRoutes::default))
+    }
+
+    /// # Mount
+    ///
+    /// Serve a directory of static files (see [StaticFiles](struct.StaticFiles.html)).
+    ///
+    /// Routes take priority over mounted files, and when mounts overlap, the one with the longest prefix is used.
+    pub async fn mount(&mut self, files: StaticFiles) {
+        self.static_files.push(files);
+        self.static_files.sort_by_key(|files| std::cmp::Reverse(files.prefix().len()));
+    }
+
+    /// # Set Fallback
+    ///
+    /// Set the route which handles requests that don't match any other route, whatever their method.
+    ///
+    /// Without a fallback, those requests are answered with `404 Not Found` (which can be customised
+    /// with [set_error_handler](#method.set_error_handler)).
+    pub async fn set_fallback(&mut self, content: Route) {
+        self.fallback = Some(content);
+    }
+
+    /// # Set Error Handler
+    ///
+    /// Set the handler which builds the response for an error status, such as 404, 405, 413 or 500.
+    ///
+    /// The handler receives the original `Request`, and the `HttpError` which caused the error. It is used for
+    /// errors the server produces itself (no matching route, a method which isn't allowed, a body which is too large),
+    /// and for errors routes return (eg, `Err(...)` from a route returning a `Result`, or a route which panics).
+    /// Responses routes build themselves (such as `Response::new(404)`) are sent as they are.
+    ///
+    /// For errors returned by routes, the route has already taken the request, so the handler gets a copy of it
+    /// without the body (`body` and `post_request` are empty).
+    pub async fn set_error_handler(&mut self, status: impl Into<StatusCode>, handler: ErrorRoute) {
+        self.error_handlers.insert(status.into(), handler);
+    }
+
+    /// # Handle Error
+    ///
+    /// Build the response for an error, using the error handler for its status if there is one.
+    pub async fn handle_error(&self, request: Request, error: HttpError) -> Response {
+        match self.error_handlers.get(&error.status) {
+            Some(handler) => handler.run(request, error).await,
+            None => error.into_response(),
+        }
+    }
+
+    /// # Run Route
+    ///
+    /// Run a route, passing any error it returns (or a panic) on to the error handlers
+    async fn run_route(&self, route: &Route, request: Request) -> Response {
+        // Error handlers need the original request, but the route takes it - so keep a copy if we'll need one.
+        // The body could be megabytes, so it is left out of the copy.
+        let original = if self.error_handlers.is_empty() {
+            None
+        } else {
+            Some(request.without_body())
+        };
+
+        let mut response = match AssertUnwindSafe(route.run(request)).catch_unwind().await {
+            Ok(response) => response,
+            Err(_) => HttpError::new(StatusCode::INTERNAL_SERVER_ERROR, "The route panicked").into_response(),
+        };
+
+        match (original, response.take_error()) {
+            (Some(original), Some(error)) if self.error_handlers.contains_key(&error.status) => {
+                self.handle_error(original, error).await
+            }
+            _ => response,
+        }
     }
 
     /// # Get Route
     ///
-    /// This function takes in the response string from the `TcpStream` and searches the hashmap
+    /// This function takes in the `Request` read from the `Connection` and searches the hashmap
     /// for the callback function associated with the route. It then checks that the route is valid,
     /// and runs it asynchrynously (using the request so that the callback can make use of the request data)
     ///
-    /// This function only runs the callback - handling POST and GET requests is up to the callback.
-    ///
-    /// If this function detects a request for static content - which it can only detect if the data is stored in
-    /// `/static/`, then it will return early with the static content, and not run any functions.
-    ///
-    /// If an error handler is not set, and a route is not found, a panic will occur.
-    pub async fn get_route(
-        &self,
-        request: String,
-        user_addr: std::net::SocketAddr,
-        is_secure: bool,
-    ) -> Result<DataType, &str> {
-        let request = Request::new(request, user_addr, is_secure).await.unwrap();
-
-        // Handle static files - check if theyre binary or text, and handle appropriately.
-        // Probably not the best method but it *works*
-        if request.uri.contains("static") {
-            let file_path = format!(".{}", request.uri);
-            return match tokio::fs::File::open(file_path).await {
-                Ok(mut file_handle) => {
-                    let mut contents = vec![];
-                    file_handle.read_to_end(&mut contents).await.unwrap();
-
-                    let result = String::from("HTTP/2 {}\r\nContent-type: image/jpeg;\r\nTransfer-Encoding: chunked\r\n\r\n");
-                    let mut result = result.into_bytes();
-
-                    // We split the data into chunks so we don't allocate a ton of data to the stack
-                    let chunks = contents.chunks(5);
-                    let mut iter_chunks = Vec::<std::io::IoSlice>::new();
-                    for chunk in chunks {
-                        iter_chunks.push(std::io::IoSlice::new(chunk));
-                    }
-
-                    // TODO: we need to figure out how to write chunked to the buffer using non-nightly features
-                    // Also, it might be worth moving to HTTP 2 for this as chunked is http 1 only
-                    let mut encoded = Vec::new();
-                    {
-                        let mut encoder = Encoder::with_chunks_size(&mut encoded, 8);
-                        encoder.write_all_vectored(&mut iter_chunks).unwrap();
-                    }
-                    result.extend(&encoded);
-
-                    match String::from_utf8(result.clone()) {
-                        Ok(_) => {
-                            let result = String::from("HTTP/2 {} {}\r\nContent-type: text/css;\r\nTransfer-Encoding: chunked\r\n\r\n");
-                            let mut result = result.into_bytes();
-                            result.extend(&encoded);
-                            let v = String::from_utf8(result).expect("This should work");
-                            return Ok(DataType::Text(v));
-                        }
-                        Err(_) => return Ok(DataType::Bytes(result)),
-                    }
-                }
-                Err(e) => {
-                    println!("Error loading static content: {}", e);
-                    Ok(DataType::Text(String::from(
-                        "ERROR - CONTENT NOT AVAILABLE",
-                    )))
-                }
-            };
+    /// The callback registered for the request's method is run. Callbacks registered with [any](#method.any) (or
+    /// [add_route](#method.add_route)) handle every other method, so it's up to them to check which method was used.
+    ///
+    /// If no route matches, but the request is under the prefix of a [mount](#method.mount), the file it asks for is sent.
+    ///
+    /// Otherwise, the fallback route is run, or a `404 Not Found` is returned if there isn't one.
+    ///
+    /// Requests with a method we don't know (eg, `PROPFIND`) are answered with `501 Not Implemented`, without running any route.
+    pub async fn get_route(&self, mut request: Request) -> Result<Response, &str> {
+        if request.method.is_none() {
+            return Ok(self.handle_error(request, StatusCode::NOT_IMPLEMENTED.into()).await);
         }
 
-        // If not static, handle the request
-        let func = match self.routes.get(&request.uri) {
-            Some(v) => v,
+        let method_routes = match self.tree.find(&request.uri) {
+            Some((v, params)) => {
+                request.params = params.into_iter().collect();
+                v
+            }
             None => {
-                println!(
-                    "Error - user requested '{}', which does not exist on this server.",
-                    request.uri
-                );
-                self.routes.get(&"err".to_string()).unwrap() // we assume we've got an error handler
+                if let Some(files) = self.static_files.iter().find(|files| files.matches(&request.uri)) {
+                    return Ok(self.serve_static(files, request).await);
+                }
+
+                return Ok(match self.fallback.as_ref() {
+                    Some(fallback) => self.run_route(fallback, request).await,
+                    None => self.handle_error(request, StatusCode::NOT_FOUND.into()).await,
+                });
             }
         };
 
-        let result = func.run(request).await;
+        // Run the callback for the method (or the one for any method), if there is one
+        if let Some(func) = request.method.and_then(|method| method_routes.find(method)) {
+            return Ok(self.run_route(func, request).await);
+        }
+
+        let mut response = if request.method == Some(HttpMethod::Options) {
+            Response::new(StatusCode::NO_CONTENT)
+        } else {
+            self.handle_error(request, StatusCode::METHOD_NOT_ALLOWED.into()).await
+        };
+        response.headers_mut().set("Allow", method_routes.allow());
+
+        Ok(response)
+    }
+
+    /// # Serve Static
+    ///
+    /// Answer a request with a static file. Only GET and HEAD requests can fetch files.
+    async fn serve_static(&self, files: &StaticFiles, request: Request) -> Response {
+        const ALLOW: &str = "GET, HEAD, OPTIONS";
+
+        let mut response = match request.method {
+            Some(HttpMethod::Get) | Some(HttpMethod::Head) => match files.serve(&request).await {
+                Ok(response) => return response,
+                Err(e) => return self.handle_error(request, e).await,
+            },
+            Some(HttpMethod::Options) => Response::new(StatusCode::NO_CONTENT),
+            _ => self.handle_error(request, StatusCode::METHOD_NOT_ALLOWED.into()).await,
+        };
+        response.headers_mut().set("Allow", ALLOW);
 
-        Ok(result)
+        response
     }
 }
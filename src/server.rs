@@ -1,37 +1,63 @@
 use std::io::BufReader;
 use std::fs::File;
+use std::future::Future;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::io::{self};
 use tokio::net::{TcpListener, TcpStream}; // Async versions of the stdlib implementation // :D
+use tokio::sync::{mpsc, watch, Semaphore};
+use tokio::time::timeout;
 
 // TLS stuff, so we can support HTTPS
 use tokio_rustls::rustls::{self, Certificate, PrivateKey};
 use tokio_rustls::{TlsAcceptor, server::TlsStream};
 use rustls_pemfile::{certs, rsa_private_keys};
 
-use crate::{Connection, connection::ConnectionType};
-use crate::Routes;
+use crate::{Connection, HttpServerBuilder, ReadError, connection::ConnectionType};
+use crate::{HttpError, HttpMethod, IntoResponse, Request, Response, Routes, StatusCode};
+
+/// How long to wait before accepting again, after the first accept error caused by running out of resources
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+/// The longest we wait before accepting again - the wait doubles after every error, up to this
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// # Error Callback
+///
+/// Called with every error the server runs into (see [set_error_callback](struct.HttpServer.html#method.set_error_callback))
+pub(crate) type ErrorCallback = Arc<dyn Fn(&ServerError) + Send + Sync>;
 
 /// # HTTP Server
 ///
-/// This struct stores the listener, which listens for incoming connections and handles them
+/// This struct stores the listeners, which listen for incoming connections and handle them
 ///
 /// **Example**:
 ///
-/// ```
+/// ```no_run
+/// # use micro_http_async::HttpServer;
+/// # #[tokio::main]
+/// # async fn main() {
 /// let http_server = HttpServer::new("127.0.0.1", "8080").await.unwrap(); // Create a new http listener
+/// # drop(http_server);
+/// # }
 /// ```
 pub struct HttpServer {
-    listener: TcpListener,
+    listeners: Vec<Listener>,
     pub routes: Routes,
 
     /* Hidden parameters */
-    read_buffer_size: usize,
+    settings: ConnectionSettings,
 
-    // TLS stuff
-    tls_acceptor: Option<TlsAcceptor>,
+    // Where errors go, so they don't take the server down
+    on_error: ErrorCallback,
+
+    // The most connections served at once
+    max_connections: Option<usize>,
+
+    // Shutdown stuff - the sender is shared with every `ShutdownHandle`, and we keep a receiver so sends always succeed
+    shutdown: Arc<watch::Sender<bool>>,
+    shutdown_requested: watch::Receiver<bool>,
 }
 
 impl HttpServer {
@@ -40,18 +66,21 @@ impl HttpServer {
     /// Create a new server, with a given IP and port
     ///
     /// **Example**
-    /// ```
+    /// ```no_run
+    /// # use micro_http_async::HttpServer;
+    /// # #[tokio::main]
+    /// # async fn main() {
     /// let http_server = HttpServer::new("127.0.0.1", "8080").await.unwrap();
+    /// # drop(http_server);
+    /// # }
     /// ```
+    ///
+    /// # Note
+    ///
+    /// Use [builder](#method.builder) to configure everything else as well.
     pub async fn new(ip: &str, port: &str) -> io::Result<Self> {
         let address = format!("{}:{}", ip, port);
-        println!("Listening on {}", address);
-        Ok(Self {
-            listener: TcpListener::bind(&address).await?,
-            routes: Routes::new().await,
-            read_buffer_size: 8192,
-            tls_acceptor: None,
-        })
+        Ok(Self::builder().bind(address).build().await?)
     }
 
 
@@ -60,8 +89,13 @@ impl HttpServer {
     /// Create a new server, with a given IP and port, and a TLS certificate and key
     /// 
     /// **Example**
-    /// ```
-    /// let http_server = HttpServer::new_tls("127.0.0.1", "8080", "cert.pem", "key.pem").await.unwrap();
+    /// ```no_run
+    /// # use micro_http_async::HttpServer;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let http_server = HttpServer::new_tls("127.0.0.1", "8080", "cert.pem".into(), "key.pem".into()).await.unwrap();
+    /// # drop(http_server);
+    /// # }
     /// ```
     /// 
     /// # Note
@@ -69,55 +103,271 @@ impl HttpServer {
     /// The certificate and key files must be in PEM and KEY formats respectively. The key must not be encrypted.
     pub async fn new_tls(ip: &str, port: &str, cert_path: PathBuf, key_path: PathBuf) -> io::Result<Self> {
         let address = format!("{}:{}", ip, port);
-        println!("Listening on {}", address);
-        let certs = load_certs(&cert_path)?;
-        let mut keys = load_keys(&key_path)?;
+        Ok(Self::builder().bind(address).tls(cert_path, key_path).build().await?)
+    }
 
-        let config = rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(certs, keys.remove(0))
-            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    /// # Builder
+    ///
+    /// Configure a new server - the address, TLS, limits, timeouts and logging - with an
+    /// [HttpServerBuilder](struct.HttpServerBuilder.html)
+    ///
+    /// **Example**
+    /// ```no_run
+    /// # use micro_http_async::HttpServer;
+    /// # use std::net::SocketAddr;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+    /// let http_server = HttpServer::builder().bind(addr).max_header_size(8 * 1024).build().await.unwrap();
+    /// # drop(http_server);
+    /// # }
+    /// ```
+    pub fn builder() -> HttpServerBuilder {
+        HttpServerBuilder::new()
+    }
+
+    /// # Local Address
+    ///
+    /// Get the address the server is listening on. Useful when it was bound to port 0, to find out which port
+    /// the OS picked. If the server listens on several addresses, this is the first of them - see
+    /// [local_addrs](#method.local_addrs) for all of them.
+    ///
+    /// **Example**
+    /// ```
+    /// # use micro_http_async::HttpServer;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let http_server = HttpServer::builder().bind("127.0.0.1:0").logging(false).build().await.unwrap();
+    /// let port = http_server.local_addr().unwrap().port();
+    /// assert_ne!(port, 0);
+    /// # }
+    /// ```
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.listeners[0].listener.local_addr()
+    }
 
-        let acceptor = TlsAcceptor::from(Arc::new(config));
+    /// # Local Addresses
+    ///
+    /// Get every address the server is listening on, in the order they were added to the
+    /// [HttpServerBuilder](struct.HttpServerBuilder.html)
+    pub fn local_addrs(&self) -> io::Result<Vec<std::net::SocketAddr>> {
+        self.listeners.iter().map(|listener| listener.listener.local_addr()).collect()
+    }
 
-        Ok(Self {
-            listener: TcpListener::bind(&address).await?,
+    /// # From Parts
+    ///
+    /// Create a server from its bound listeners and settings (see [HttpServerBuilder::build](struct.HttpServerBuilder.html#method.build)).
+    /// There must be at least one listener.
+    pub(crate) async fn from_parts(
+        listeners: Vec<Listener>,
+        settings: ConnectionSettings,
+        on_error: ErrorCallback,
+        max_connections: Option<usize>,
+    ) -> Self {
+        let (shutdown, shutdown_requested) = watch::channel(false);
+        Self {
+            listeners,
             routes: Routes::new().await,
-            read_buffer_size: 8192,
-            tls_acceptor: Some(acceptor),
-        })
+            settings,
+            on_error,
+            max_connections,
+            shutdown: Arc::new(shutdown),
+            shutdown_requested,
+        }
     }
 
     /// # Listen
     ///
     /// Listen for new connections.
     ///
-    /// Run `handle_connection` upon connection.
+    /// Every accepted connection is handed off to its own task, so a slow client (or a slow TLS handshake)
+    /// never blocks anyone else. The routes are moved into an `Arc` and shared immutably between those tasks,
+    /// which lets handlers run in parallel on the multi-threaded runtime.
     /// 
     /// # Note
     /// 
-    /// If you are using TLS, this function will not return until the server is shut down.
+    /// This function will not return until the server is shut down with a [ShutdownHandle](struct.ShutdownHandle.html)
+    /// (see [listen_with_shutdown](#method.listen_with_shutdown) for how shutting down works).
     /// 
-    /// All errors will be printed to the console, instead of panicking.
+    /// Errors never take the server down - a connection which fails is closed, and the server carries on.
+    /// They are passed to the error callback (see [set_error_callback](#method.set_error_callback)), which
+    /// prints them by default.
     pub async fn listen(&mut self) -> Result<(), &'static str> {
+        self.listen_with_shutdown(futures::future::pending()).await
+    }
+
+    /// # Listen With Shutdown
+    ///
+    /// Listen for new connections (see [listen](#method.listen)) until `signal` completes, or a
+    /// [ShutdownHandle](struct.ShutdownHandle.html) is used, then shut down gracefully:
+    ///
+    /// 1. No more connections are accepted.
+    /// 2. Idle keep-alive connections are closed, and requests which are in flight are answered (with `Connection: close`).
+    /// 3. Once every connection has closed, or the shutdown timeout passes (see [set_shutdown_timeout](#method.set_shutdown_timeout)),
+    ///    whichever comes first, any connections still open are dropped and this function returns.
+    ///
+    /// The routes are handed back to `routes` once it returns, so the server can listen again.
+    ///
+    /// **Example**
+    /// ```no_run
+    /// # use micro_http_async::HttpServer;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut http_server = HttpServer::new("127.0.0.1", "8080").await.unwrap();
+    ///
+    /// // Shut down when the task is sent a message
+    /// let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    /// # drop(stop);
+    /// http_server.listen_with_shutdown(async { stopped.await.ok(); }).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn listen_with_shutdown(&mut self, signal: impl Future<Output = ()>) -> Result<(), &'static str> {
+        // Routes can no longer be modified once we start listening, so share them between connection tasks
+        let routes = Arc::new(std::mem::take(&mut self.routes));
+
+        // Tells connection tasks when to start draining, and when to give up
+        let (state, state_receiver) = watch::channel(ServerState::Running);
+        // Every connection task holds a clone of `tasks`, so once they have all finished, `recv` returns `None`
+        let (tasks, mut tasks_finished) = mpsc::channel::<()>(1);
+
+        let mut shutdown_requested = self.shutdown_requested.clone();
+        tokio::pin!(signal);
+
+        // Each connection holds a permit, so once they are all taken we stop accepting until one closes
+        let connection_limit = self.max_connections.map(|max_connections| Arc::new(Semaphore::new(max_connections)));
+
+        let mut backoff = MIN_ACCEPT_BACKOFF;
+        // The listener which is checked first for a connection, so that a busy one can't starve the others
+        let mut first_listener = 0;
         loop {
-            let (socket, addr) = self.listener.accept().await.unwrap(); // Accept an incoming connection
-
-            let acceptor = self.tls_acceptor.clone();
-
-            // Check if we need to use TLS
-            if let Some(ref tls_acceptor) = acceptor {
-                let tls_socket = tls_acceptor.accept(socket).await;
-                if let Ok(tls_socket) = tls_socket {
-                    self.handle_connection_tls(tls_socket, addr).await.unwrap();
-                }else{
-                    // Safely output the error without panicking
-                    eprintln!("Error occurred while accepting TLS connection: {:?}", tls_socket);
+            let permit = match &connection_limit {
+                Some(connection_limit) => tokio::select! {
+                    permit = Arc::clone(connection_limit).acquire_owned() => permit.ok(), // The semaphore is never closed
+                    _ = &mut signal => break,
+                    _ = wait_for_shutdown(&mut shutdown_requested) => break,
+                },
+                None => None,
+            };
+
+            let accepted = tokio::select! {
+                accepted = accept(&self.listeners, first_listener) => accepted, // Accept an incoming connection, on whichever listener gets one first
+                _ = &mut signal => break,
+                _ = wait_for_shutdown(&mut shutdown_requested) => break,
+            };
+
+            first_listener = (first_listener + 1) % self.listeners.len();
+
+            let (socket, addr, acceptor) = match accepted {
+                Ok(accepted) => {
+                    backoff = MIN_ACCEPT_BACKOFF;
+                    accepted
                 }
-            }else{
-                self.handle_connection(socket, addr).await.unwrap(); // Handle it
-            }
+                Err(error) => {
+                    // The client gave up before we got to it - nothing to do but move on to the next one
+                    let connection_error = is_connection_error(&error);
+                    (self.on_error)(&ServerError::Accept(error));
+                    if connection_error {
+                        continue;
+                    }
+
+                    // Most likely we're out of file descriptors (or memory), and accepting again straight away
+                    // would fail the same way - so give connections time to close first
+                    tokio::select! {
+                        _ = tokio::time::sleep(backoff) => {}
+                        _ = &mut signal => break,
+                        _ = wait_for_shutdown(&mut shutdown_requested) => break,
+                    }
+                    backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+
+            let routes = Arc::clone(&routes);
+            let settings = self.settings;
+            let on_error = Arc::clone(&self.on_error);
+            let mut state = state_receiver.clone();
+            let task = tasks.clone();
+
+            tokio::spawn(async move {
+                // Held until the connection is finished
+                let _task = task;
+                let _permit = permit;
+                let connection_state = state.clone();
+
+                let connection = async move {
+                    // Check if we need to use TLS
+                    let result = match acceptor {
+                        // Don't let a client which never finishes the handshake hold on to the connection
+                        Some(tls_acceptor) => match timeout(settings.handshake_timeout, tls_acceptor.accept(socket)).await {
+                            Ok(Ok(tls_socket)) => {
+                                Self::handle_connection_tls(tls_socket, addr, routes, settings, connection_state).await
+                            }
+                            Ok(Err(error)) => Err(ServerError::Handshake { addr, error }),
+                            Err(_) => Err(ServerError::Handshake {
+                                addr,
+                                error: io::Error::new(io::ErrorKind::TimedOut, "the handshake timed out"),
+                            }),
+                        },
+                        None => Self::handle_connection(socket, addr, routes, settings, connection_state).await, // Handle it
+                    };
+
+                    if let Err(e) = result {
+                        on_error(&e);
+                    }
+                };
+
+                // Drop the connection if the server runs out of time to shut down
+                tokio::select! {
+                    _ = connection => {}
+                    _ = wait_for_state(&mut state, ServerState::Stopped) => {}
+                }
+            });
+        }
+
+        // Stop taking new requests, and close idle connections
+        let _ = state.send(ServerState::Draining);
+        drop(tasks);
+
+        if timeout(self.settings.shutdown_timeout, tasks_finished.recv()).await.is_err() {
+            // Out of time - drop every connection which is still open, and wait for their tasks to notice
+            let _ = state.send(ServerState::Stopped);
+            let _ = tasks_finished.recv().await;
+        }
+
+        // Every connection task has finished, so nothing else holds the routes - put them back, so the server can listen again
+        if let Ok(routes) = Arc::try_unwrap(routes) {
+            self.routes = routes;
+        }
+        // The shutdown has been dealt with, so the next `listen` doesn't stop straight away
+        let _ = self.shutdown.send(false);
+
+        Ok(())
+    }
+
+    /// # Shutdown Handle
+    ///
+    /// Get a handle which can shut the server down gracefully, once it is listening. The handle can be cloned,
+    /// and sent to other tasks.
+    ///
+    /// **Example**
+    /// ```no_run
+    /// # use micro_http_async::HttpServer;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut http_server = HttpServer::new("127.0.0.1", "8080").await.unwrap();
+    /// let shutdown = http_server.shutdown_handle();
+    ///
+    /// tokio::spawn(async move {
+    ///     tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+    ///     shutdown.shutdown();
+    /// });
+    ///
+    /// http_server.listen().await.unwrap(); // Returns once the server has shut down
+    /// # }
+    /// ```
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            sender: Arc::clone(&self.shutdown),
         }
     }
 
@@ -127,33 +377,26 @@ impl HttpServer {
     /// handle the response and write it back to the user.
     ///
     /// This function should only be called by the `HttpServer`, as it should only be run upon accepting
-    /// a new connection
+    /// a new connection. It runs inside its own task, so it only borrows what it needs from the server.
     ///
     /// We define the content to return using the `Routes` struct in `HttpServer`
     ///
     /// It returns a Result for better error handling if something goes wrong at any point during I/O operations
     async fn handle_connection(
-        &mut self,
         stream: TcpStream,
         addr: std::net::SocketAddr,
-    ) -> Result<(), &str> {
-        let mut connection = Connection::new(ConnectionType::Plain(Some(stream)), self.read_buffer_size); // Create our connection handler
-
-        let request_str = connection.read_to_string().await.unwrap(); // get a string value from the recieved data
-
-        // only needs the request and address as it constructs a `Request` to get the route and more info
-        let ret_str = self.routes.get_route(request_str, addr, false).await.unwrap();
-
-        match ret_str {
-            crate::DataType::Text(text) => {
-                connection.write_string(text).await.unwrap();
-            }
-            crate::DataType::Bytes(bytes) => {
-                connection.write_bytes(bytes).await.unwrap();
-            }
-        }
+        routes: Arc<Routes>,
+        settings: ConnectionSettings,
+        state: watch::Receiver<ServerState>,
+    ) -> Result<(), ServerError> {
+        let connection = Connection::new(
+            ConnectionType::Plain(Some(stream)),
+            settings.read_buffer_size,
+            settings.max_header_size,
+            settings.max_body_size,
+        ); // Create our connection handler
 
-        Ok(()) // Return the future
+        Self::serve_connection(connection, addr, false, routes, settings, state).await
     }
 
     /// # Handle Connection TLS
@@ -166,51 +409,482 @@ impl HttpServer {
     /// 
     /// Read [handle_connection](#method.handle_connection) for more information.    
     async fn handle_connection_tls(
-        &mut self,
         stream: TlsStream<TcpStream>,
         addr: std::net::SocketAddr,
-    ) -> Result<(), &str> {
-        let mut connection = Connection::new(ConnectionType::TLS(Some(stream)), self.read_buffer_size); // Create our connection handler
+        routes: Arc<Routes>,
+        settings: ConnectionSettings,
+        state: watch::Receiver<ServerState>,
+    ) -> Result<(), ServerError> {
+        let connection = Connection::new(
+            ConnectionType::TLS(Some(stream)),
+            settings.read_buffer_size,
+            settings.max_header_size,
+            settings.max_body_size,
+        ); // Create our connection handler
+
+        Self::serve_connection(connection, addr, true, routes, settings, state).await
+    }
+
+    /// # Serve Connection
+    ///
+    /// Read requests from the connection and answer them, one after the other, until the client
+    /// closes the connection, asks us to close it, stays idle for longer than the keep-alive timeout,
+    /// reaches the maximum number of requests allowed per connection, or the server shuts down.
+    ///
+    /// Returns the error which closed the connection, if it was closed because of one.
+    async fn serve_connection(
+        mut connection: Connection,
+        addr: std::net::SocketAddr,
+        is_secure: bool,
+        routes: Arc<Routes>,
+        settings: ConnectionSettings,
+        mut state: watch::Receiver<ServerState>,
+    ) -> Result<(), ServerError> {
+        let mut requests_served = 0;
+
+        loop {
+            // Wait for the next request to start, but don't hold on to idle connections forever (or once the server
+            // is shutting down). A request the client has already sent is still answered.
+            // A new connection gets as long as a whole request would to start sending one.
+            let idle_timeout = if requests_served == 0 { settings.request_timeout } else { settings.keep_alive_timeout };
+            let waited = tokio::select! {
+                biased;
+                waited = timeout(idle_timeout, connection.wait_for_request()) => waited,
+                _ = wait_for_state(&mut state, ServerState::Draining) => break,
+            };
+            match waited {
+                Ok(Ok(())) => {}
+                Ok(Err(ReadError::Io(error))) => return Err(ServerError::Read { addr, error }),
+                Ok(Err(_)) => break, // The client closed the connection
+                Err(_) => break, // The client was idle for too long
+            }
+
+            // The request has started, so it gets to finish even if the server starts shutting down. Large uploads
+            // take a while, so it has longer to arrive in full than the connection may sit idle.
+            let read = timeout(settings.request_timeout, connection.read_to_vec()).await;
+            let request_bytes = match read {
+                Ok(Ok(request_bytes)) => request_bytes, // get the raw bytes of the request
+                Ok(Err(ReadError::Closed)) => break, // The client closed the connection
+                Ok(Err(ReadError::Io(error))) => return Err(ServerError::Read { addr, error }),
+                Ok(Err(ReadError::BodyTooLarge(head))) => {
+                    // We have the headers, so the error handlers can still see the request
+                    let error = HttpError::new(StatusCode::PAYLOAD_TOO_LARGE, "The request body is too large");
+                    let response = match Request::new(head, addr, is_secure).await {
+                        Ok(request) => routes.handle_error(request, error).await,
+                        Err(_) => error.into_response(),
+                    };
+                    let response = response.header("Connection", "close");
+                    connection.write_bytes(response.into_bytes()).await.map_err(|e| ServerError::write(addr, e))?;
+                    break;
+                }
+                Ok(Err(e)) => {
+                    // We can't tell where the request ends, so answer with an error and close the connection
+                    let status = match e {
+                        ReadError::HeadersTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                        _ => StatusCode::BAD_REQUEST,
+                    };
+                    let response = Response::new(status).header("Connection", "close");
+                    connection.write_bytes(response.into_bytes()).await.map_err(|e| ServerError::write(addr, e))?;
+                    break;
+                }
+                Err(_) => {
+                    // The client took too long to send the request. Part of it is still to come, so close the connection.
+                    let response = Response::new(StatusCode::REQUEST_TIMEOUT).header("Connection", "close");
+                    connection.write_bytes(response.into_bytes()).await.map_err(|e| ServerError::write(addr, e))?;
+                    break;
+                }
+            };
+
+            let request = match Request::new(request_bytes, addr, is_secure).await {
+                Ok(request) => request,
+                Err(_) => {
+                    // The request can't be understood, so there's nothing to route
+                    let response = Response::new(StatusCode::BAD_REQUEST).header("Connection", "close");
+                    connection.write_bytes(response.into_bytes()).await.map_err(|e| ServerError::write(addr, e))?;
+                    break;
+                }
+            };
+            requests_served += 1;
 
-        let request_str = connection.read_to_string().await.unwrap(); // get a string value from the recieved data
+            let mut keep_alive = request.keep_alive() && requests_served < settings.max_requests_per_connection;
 
-        // only needs the request and address as it constructs a `Request` to get the route and more info
-        let ret_str = self.routes.get_route(request_str, addr,true).await.unwrap();
+            // only needs the request as it holds the route and more info
+            let include_body = request.method != Some(HttpMethod::Head);
 
-        match ret_str {
-            crate::DataType::Text(text) => {
-                connection.write_string(text).await.unwrap();
+            let mut response = match routes.get_route(request).await {
+                Ok(response) => response,
+                Err(_) => Response::new(StatusCode::INTERNAL_SERVER_ERROR),
+            };
+
+            // The server started shutting down while the request was being handled, so this is the last one
+            if *state.borrow() != ServerState::Running {
+                keep_alive = false;
             }
-            crate::DataType::Bytes(bytes) => {
-                connection.write_bytes(bytes).await.unwrap();
+
+            // A handler which sets `Connection: close` wants the connection closed after its response
+            match response.headers().get("Connection") {
+                Some(connection) if connection.to_ascii_lowercase().contains("close") => keep_alive = false,
+                _ => {
+                    let connection = if keep_alive { "keep-alive" } else { "close" };
+                    response.headers_mut().set("Connection", connection);
+                }
+            }
+
+            // File bodies are streamed, so the write can fail part way through (eg, the file is shorter than
+            // it was when the response was built). The client can't tell where the response ends, so close.
+            connection.write_response(response, include_body).await.map_err(|e| ServerError::write(addr, e))?;
+
+            if !keep_alive {
+                break;
             }
         }
 
-        Ok(()) // Return the future
+        Ok(())
     }
 
     /// # Set Read Buffer Size
     /// 
     /// Set the read buffer size for the server. The default value is 8192 bytes.
     pub async fn set_read_buffer_size(&mut self, size: usize) -> Result<(), &'static str> {
-        self.read_buffer_size = size;
+        if size == 0 {
+            return Err("The read buffer size must be greater than zero");
+        }
+        self.settings.read_buffer_size = size;
         
         Ok(())
     }
+
+    /// # Set Max Body Size
+    ///
+    /// Set the largest request body the server will accept. Requests with a larger body are answered with
+    /// `413 Payload Too Large`. The default value is 10MB.
+    pub async fn set_max_body_size(&mut self, size: usize) -> Result<(), &'static str> {
+        self.settings.max_body_size = size;
+
+        Ok(())
+    }
+
+    /// # Set Keep Alive Timeout
+    ///
+    /// Set how long a persistent connection may stay idle between requests before it is closed.
+    /// The default value is 5 seconds.
+    pub async fn set_keep_alive_timeout(&mut self, keep_alive_timeout: Duration) -> Result<(), &'static str> {
+        if keep_alive_timeout.is_zero() {
+            return Err("The keep alive timeout must be greater than zero (set the max requests per connection to 1 to disable keep-alive)");
+        }
+        self.settings.keep_alive_timeout = keep_alive_timeout;
+
+        Ok(())
+    }
+
+    /// # Set Request Timeout
+    ///
+    /// Set how long a client has to send a whole request, headers and body, once it has started sending it.
+    /// Requests which take longer are answered with `408 Request Timeout`. The default value is 30 seconds.
+    pub async fn set_request_timeout(&mut self, request_timeout: Duration) -> Result<(), &'static str> {
+        if request_timeout.is_zero() {
+            return Err("The request timeout must be greater than zero");
+        }
+        self.settings.request_timeout = request_timeout;
+
+        Ok(())
+    }
+
+    /// # Set Handshake Timeout
+    ///
+    /// Set how long a client has to finish the TLS handshake before its connection is closed. The default value is 10 seconds.
+    pub async fn set_handshake_timeout(&mut self, handshake_timeout: Duration) -> Result<(), &'static str> {
+        if handshake_timeout.is_zero() {
+            return Err("The handshake timeout must be greater than zero");
+        }
+        self.settings.handshake_timeout = handshake_timeout;
+
+        Ok(())
+    }
+
+    /// # Set Shutdown Timeout
+    ///
+    /// Set how long the server waits for requests which are in flight to finish when it shuts down, before
+    /// dropping their connections. The default value is 30 seconds.
+    pub async fn set_shutdown_timeout(&mut self, shutdown_timeout: Duration) -> Result<(), &'static str> {
+        self.settings.shutdown_timeout = shutdown_timeout;
+
+        Ok(())
+    }
+
+    /// # Set Error Callback
+    ///
+    /// Set the function which is called with every error the server runs into, such as a connection being
+    /// reset, a TLS handshake failing or running out of file descriptors. None of them stop the server, so this
+    /// is the place to log them. By default, they are printed to stderr.
+    ///
+    /// **Example**
+    /// ```no_run
+    /// # use micro_http_async::{HttpServer, ServerError};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut http_server = HttpServer::new("127.0.0.1", "8080").await.unwrap();
+    /// http_server.set_error_callback(|error: &ServerError| {
+    ///     // Clients hanging up aren't worth logging
+    ///     if !matches!(error, ServerError::Read { .. }) {
+    ///         eprintln!("server error: {}", error);
+    ///     }
+    /// }).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn set_error_callback(&mut self, callback: impl Fn(&ServerError) + Send + Sync + 'static) -> Result<(), &'static str> {
+        self.on_error = Arc::new(callback);
+
+        Ok(())
+    }
+
+    /// # Set Max Requests Per Connection
+    ///
+    /// Set how many requests can be served over a single connection before it is closed.
+    /// The default value is 100. Setting it to 1 disables persistent connections.
+    pub async fn set_max_requests_per_connection(&mut self, max_requests: usize) -> Result<(), &'static str> {
+        if max_requests == 0 {
+            return Err("A connection must be able to serve at least one request");
+        }
+        self.settings.max_requests_per_connection = max_requests;
+
+        Ok(())
+    }
+}
+
+/// # Listener
+///
+/// A listener the server accepts connections on, and the TLS acceptor if connections to it use TLS
+pub(crate) struct Listener {
+    /// The bound listener
+    pub(crate) listener: TcpListener,
+    /// Performs the TLS handshake with each connection, if it uses TLS
+    pub(crate) tls_acceptor: Option<TlsAcceptor>,
+}
+
+/// Accept a connection on whichever listener gets one first, along with the TLS acceptor of that listener.
+///
+/// If several listeners have a connection waiting, the first of them from `first` on (wrapping around) gets to go.
+async fn accept(listeners: &[Listener], first: usize) -> io::Result<(TcpStream, std::net::SocketAddr, Option<TlsAcceptor>)> {
+    let accepts = listeners.iter().cycle().skip(first).take(listeners.len()).map(|listener| {
+        Box::pin(async move {
+            let (socket, addr) = listener.listener.accept().await?;
+            Ok((socket, addr, listener.tls_acceptor.clone()))
+        })
+    });
+
+    futures::future::select_all(accepts).await.0
+}
+
+/// # Connection Settings
+///
+/// The settings every connection task needs from the `HttpServer`. They are copied into
+/// each task, so that the tasks don't need to borrow the server.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ConnectionSettings {
+    /// The read buffer size
+    pub(crate) read_buffer_size: usize,
+    /// The largest request head we accept
+    pub(crate) max_header_size: usize,
+    /// The largest request body we accept
+    pub(crate) max_body_size: usize,
+    /// How long a connection may stay idle between requests
+    pub(crate) keep_alive_timeout: Duration,
+    /// How long a client has to send a whole request
+    pub(crate) request_timeout: Duration,
+    /// How long a client has to finish the TLS handshake
+    pub(crate) handshake_timeout: Duration,
+    /// How many requests a single connection may serve
+    pub(crate) max_requests_per_connection: usize,
+    /// How long in flight requests have to finish when the server shuts down
+    pub(crate) shutdown_timeout: Duration,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        Self {
+            read_buffer_size: 8192,
+            max_header_size: 16 * 1024,
+            max_body_size: 10 * 1024 * 1024,
+            keep_alive_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(30),
+            handshake_timeout: Duration::from_secs(10),
+            max_requests_per_connection: 100,
+            shutdown_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// # Server Error
+///
+/// An error the server ran into while accepting or serving a connection. These never stop the server - they are
+/// passed to the error callback (see [HttpServer::set_error_callback](struct.HttpServer.html#method.set_error_callback)).
+#[derive(Debug)]
+pub enum ServerError {
+    /// A connection could not be accepted. If we have run out of resources (such as file descriptors),
+    /// the server waits a little before accepting again.
+    Accept(io::Error),
+    /// The TLS handshake with a client failed, or didn't finish in time
+    Handshake {
+        /// The address of the client
+        addr: std::net::SocketAddr,
+        /// Why the handshake failed
+        error: io::Error,
+    },
+    /// Reading a request failed, for example because the client reset the connection
+    Read {
+        /// The address of the client
+        addr: std::net::SocketAddr,
+        /// Why the read failed
+        error: io::Error,
+    },
+    /// Writing a response failed, for example because the client closed the connection early, or a file
+    /// being sent was shorter than expected
+    Write {
+        /// The address of the client
+        addr: std::net::SocketAddr,
+        /// Why the write failed
+        error: io::Error,
+    },
+}
+
+impl ServerError {
+    /// A failed write - the connection returns boxed errors, but they are I/O errors underneath
+    fn write(addr: std::net::SocketAddr, error: Box<dyn std::error::Error>) -> Self {
+        let error = match error.downcast::<io::Error>() {
+            Ok(error) => *error,
+            Err(error) => io::Error::other(error.to_string()),
+        };
+        ServerError::Write { addr, error }
+    }
+}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerError::Accept(error) => write!(f, "error accepting connection: {}", error),
+            ServerError::Handshake { addr, error } => write!(f, "TLS handshake with {} failed: {}", addr, error),
+            ServerError::Read { addr, error } => write!(f, "error reading from {}: {}", addr, error),
+            ServerError::Write { addr, error } => write!(f, "error writing to {}: {}", addr, error),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServerError::Accept(error)
+            | ServerError::Handshake { error, .. }
+            | ServerError::Read { error, .. }
+            | ServerError::Write { error, .. } => Some(error),
+        }
+    }
+}
+
+/// The default error callback - print the error to stderr
+pub(crate) fn print_error(error: &ServerError) {
+    eprintln!("{}", error);
+}
+
+/// Whether an accept error was caused by the connection itself (the client gave up before it was accepted),
+/// rather than the server, so accepting the next connection should work
+fn is_connection_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+    )
+}
+
+/// # Shutdown Handle
+///
+/// Shuts a listening `HttpServer` down gracefully (see [HttpServer::listen_with_shutdown](struct.HttpServer.html#method.listen_with_shutdown)).
+/// Get one with [HttpServer::shutdown_handle](struct.HttpServer.html#method.shutdown_handle).
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    /// Shared with the server, which waits for it to send `true`
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    /// # Shutdown
+    ///
+    /// Tell the server to shut down. `listen` returns once it has finished. If the server isn't listening yet,
+    /// it shuts down as soon as it starts.
+    pub fn shutdown(&self) {
+        // The server keeps a receiver, so this can't fail
+        let _ = self.sender.send(true);
+    }
+}
+
+/// # Server State
+///
+/// What connection tasks should be doing, as the server shuts down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ServerState {
+    /// Serving requests as normal
+    Running,
+    /// Shutting down - finish the request in flight, but don't wait for any more
+    Draining,
+    /// Out of time to shut down - drop the connection
+    Stopped,
+}
+
+/// Wait until a `ShutdownHandle` asks the server to shut down
+async fn wait_for_shutdown(shutdown_requested: &mut watch::Receiver<bool>) {
+    while !*shutdown_requested.borrow() {
+        if shutdown_requested.changed().await.is_err() {
+            // Every handle (and the server) is gone, so nothing can ask us to shut down any more
+            futures::future::pending::<()>().await;
+        }
+    }
 }
 
+/// Wait until the server reaches `target` (or a later state). If the server has stopped listening altogether,
+/// there is nothing left to wait for.
+async fn wait_for_state(state: &mut watch::Receiver<ServerState>, target: ServerState) {
+    while *state.borrow() < target {
+        if state.changed().await.is_err() {
+            return;
+        }
+    }
+}
 
 // Helpful TLS functions for reading certificates and keys
 
+/// # Load TLS Acceptor
+///
+/// Load a certificate and key, and create the acceptor which performs TLS handshakes with them.
+pub(crate) fn load_tls_acceptor(cert_path: &Path, key_path: &Path) -> io::Result<TlsAcceptor> {
+    let certs = load_certs(cert_path)?;
+    if certs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no certificates found in {}", cert_path.display())));
+    }
+    let key = load_keys(key_path)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("no RSA private keys found in {}", key_path.display())))?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
 /// # Load Certificates
 /// 
 /// Load certificates from a given path.
 /// 
 /// Returns a `Vec<Certificate>`
 fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
-    let buf_reader = &mut BufReader::new(File::open(path).unwrap());
+    let buf_reader = &mut BufReader::new(open(path)?);
     certs(buf_reader)
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid cert"))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid certificate in {}", path.display())))
         .map(|mut certs| certs.drain(..).map(Certificate).collect())
 }
 
@@ -221,8 +895,13 @@ fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
 /// 
 /// Returns a `Vec<PrivateKey>`
 fn load_keys(path: &Path) -> io::Result<Vec<PrivateKey>> {
-    let buf_reader = &mut BufReader::new(File::open(path).unwrap());
+    let buf_reader = &mut BufReader::new(open(path)?);
     rsa_private_keys(buf_reader)
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid key"))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid key in {}", path.display())))
         .map(|mut keys| keys.drain(..).map(PrivateKey).collect())
-}
\ No newline at end of file
+}
+
+/// Open a file, saying which file in the error if it can't be opened
+fn open(path: &Path) -> io::Result<File> {
+    File::open(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
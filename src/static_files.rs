@@ -0,0 +1,673 @@
+use crate::date::{format_http_date, parse_http_date, truncate_to_seconds};
+use crate::listing;
+use crate::mime::MimeTypes;
+use crate::path::{percent_decode, split_path};
+use crate::range::{parse_range, ByteRange, Ranges};
+use crate::{Body, HttpError, Request, Response, StatusCode};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The file served for requests for a directory
+const INDEX_FILE: &str = "index.html";
+
+/// The precompressed copies of a file which can be sent instead of it - the `Content-Encoding` of each, and the extension
+/// added to the file's name. Earlier ones compress better, so are preferred.
+const PRECOMPRESSED: &[(&str, &str)] = &[("br", ".br"), ("gzip", ".gz")];
+
+/// # Static Files
+///
+/// Serves the files in a directory, under a path prefix. For example, `StaticFiles::new("/static", "./static")`
+/// serves `./static/css/site.css` at `/static/css/site.css`.
+///
+/// Requests can't reach anything outside of the directory. Paths containing `..` are refused with `403 Forbidden`,
+/// as are symlinks which point outside of it, and files which don't exist are answered with `404 Not Found`.
+/// Hidden files and directories (whose names start with `.`, such as `.env` or `.git`) are answered with
+/// `404 Not Found` too, unless they are turned on with [hidden_files](#method.hidden_files).
+/// Both go through the error handlers set with [Routes::set_error_handler](struct.Routes.html#method.set_error_handler).
+///
+/// The `Content-Type` of each file is picked from its extension, using a table of the common web types
+/// (which can be extended with [mime_type](#method.mime_type)). Text types are sent with `charset=utf-8`.
+///
+/// Parts of files can be fetched with the `Range` header (so downloads can be resumed, and videos seeked), which
+/// is answered with `206 Partial Content`, or `416 Range Not Satisfiable` if none of the ranges are in the file.
+/// Requests for more than one range get a `multipart/byteranges` response.
+///
+/// Files are sent with an `ETag` and `Last-Modified` taken from their metadata, so clients can revalidate their
+/// cached copies with `If-None-Match` and `If-Modified-Since` - if the file hasn't changed, `304 Not Modified` is sent
+/// instead. How long clients may cache files for is set with [cache_control](#method.cache_control).
+///
+/// Requests for a directory are sent its `index.html`. Directory paths without a trailing slash (eg, `/static/docs`) are
+/// redirected to the path with one, so that relative links in the page work. Directories without an `index.html` are
+/// answered with `404 Not Found`, unless listings are turned on with [directory_listing](#method.directory_listing).
+///
+/// If a file has a precompressed copy next to it (eg, `app.js.br` or `app.js.gz` for `app.js`) and the client's
+/// `Accept-Encoding` allows it, the copy is sent instead, with a `Content-Encoding` header. This can be turned off
+/// with [precompressed](#method.precompressed).
+///
+/// Single page apps, which route on the client, can set a fallback file with [spa_fallback](#method.spa_fallback). It is
+/// sent for requests which don't match a file, so long as the path has no extension - missing assets (such as
+/// `/static/app.js`) are still answered with `404 Not Found`.
+///
+/// **Example**
+/// ```
+/// # use micro_http_async::{Routes, StaticFiles};
+/// # #[tokio::main]
+/// # async fn main() {
+/// # let mut routes = Routes::new().await;
+/// let files = StaticFiles::new("/static", "./static")
+///     .mime_type("glb", "model/gltf-binary")
+///     .default_mime_type("text/plain");
+///
+/// // Asset names include a hash of their contents, so they never change
+/// let assets = StaticFiles::new("/assets", "./dist/assets").cache_control("public, max-age=31536000, immutable");
+///
+/// routes.mount(assets).await;
+///
+/// routes.mount(files).await;
+/// # }
+/// ```
+pub struct StaticFiles {
+    /// The segments of the path the files are served under
+    prefix: Vec<String>,
+    /// The directory the files are served from
+    root: PathBuf,
+    /// The content type of each file extension
+    mime_types: MimeTypes,
+    /// The `Cache-Control` header sent with each file
+    cache_control: Option<String>,
+    /// Whether directories without an index file are listed
+    directory_listing: bool,
+    /// Whether files and directories whose names start with `.` are served (and listed)
+    hidden_files: bool,
+    /// Whether precompressed copies of files are sent to clients which accept them
+    precompressed: bool,
+    /// The file sent for paths without an extension which don't match a file, relative to the root
+    spa_fallback: Option<PathBuf>,
+}
+
+impl StaticFiles {
+    /// # New
+    ///
+    /// Serve the files in the `root` directory under the path `prefix`
+    pub fn new(prefix: &str, root: impl Into<PathBuf>) -> Self {
+        Self {
+            prefix: split_path(prefix).map(|segment| segment.to_string()).collect(),
+            root: root.into(),
+            mime_types: MimeTypes::default(),
+            cache_control: None,
+            directory_listing: false,
+            hidden_files: false,
+            precompressed: true,
+            spa_fallback: None,
+        }
+    }
+
+    /// # Mime Type
+    ///
+    /// Set the content type of files with an extension (eg, `"glb"`), replacing the built in one if there is one
+    pub fn mime_type(mut self, extension: &str, mime: &str) -> Self {
+        self.mime_types.insert(extension, mime);
+        self
+    }
+
+    /// # Default Mime Type
+    ///
+    /// Set the content type of files whose extension isn't known. Defaults to `application/octet-stream`.
+    pub fn default_mime_type(mut self, mime: &str) -> Self {
+        self.mime_types.set_default(mime);
+        self
+    }
+
+    /// # Cache Control
+    ///
+    /// Set the `Cache-Control` header sent with each file (eg, `"no-cache"`, or `"public, max-age=31536000, immutable"`
+    /// for files whose names change when their contents do). By default no `Cache-Control` is sent.
+    pub fn cache_control(mut self, value: &str) -> Self {
+        self.cache_control = Some(value.to_string());
+        self
+    }
+
+    /// # Directory Listing
+    ///
+    /// Set whether directories without an `index.html` are listed, with the name, size and modification time of each
+    /// entry (hidden entries, whose names start with `.`, are left out unless [hidden_files](#method.hidden_files) is turned on).
+    /// Listings are HTML, or JSON for requests which
+    /// ask for `application/json` in their `Accept` header. Turned off by default.
+    pub fn directory_listing(mut self, enabled: bool) -> Self {
+        self.directory_listing = enabled;
+        self
+    }
+
+    /// # Hidden Files
+    ///
+    /// Set whether files and directories whose names start with `.` (such as `.env`, or `.git/config`) are served and
+    /// listed. They often hold secrets, or files which were never meant to be published, so they are turned off by default.
+    pub fn hidden_files(mut self, enabled: bool) -> Self {
+        self.hidden_files = enabled;
+        self
+    }
+
+    /// # Precompressed
+    ///
+    /// Set whether precompressed copies of files (`.br` and `.gz`) are sent to clients which accept them. Turned on by default.
+    pub fn precompressed(mut self, enabled: bool) -> Self {
+        self.precompressed = enabled;
+        self
+    }
+
+    /// # Spa Fallback
+    ///
+    /// Set the file (relative to the root directory, eg `"index.html"`) sent for requests which don't match a file, so
+    /// that a single page app's router can handle them. Only paths without an extension (eg, `/dashboard/settings`) get
+    /// the fallback - missing assets are still answered with `404 Not Found`. Routes take priority over mounts, so
+    /// API routes are unaffected.
+    pub fn spa_fallback(mut self, file: impl Into<PathBuf>) -> Self {
+        self.spa_fallback = Some(file.into());
+        self
+    }
+
+    /// # Prefix
+    ///
+    /// The path the files are served under
+    pub fn prefix(&self) -> String {
+        format!("/{}", self.prefix.join("/"))
+    }
+
+    /// # Root
+    ///
+    /// The directory the files are served from
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// # Matches
+    ///
+    /// Check whether a request path is under the prefix the files are served under
+    pub fn matches(&self, path: &str) -> bool {
+        self.relative_segments(path).is_some()
+    }
+
+    /// # Serve
+    ///
+    /// Build the response for the file a request asks for. The file is streamed from disk when the response is sent.
+    ///
+    /// Fails with `404 Not Found` if the file doesn't exist (or the request isn't under the prefix), and
+    /// `403 Forbidden` if the path tries to leave the root directory. If there is a SPA fallback, it is sent
+    /// instead of a `404 Not Found` for paths without an extension.
+    pub async fn serve(&self, request: &Request) -> Result<Response, HttpError> {
+        let result = self.serve_path(request).await;
+        let fallback = match (&result, self.spa_fallback.as_ref()) {
+            (Err(e), Some(fallback)) if e.status == StatusCode::NOT_FOUND => fallback,
+            _ => return result,
+        };
+        // Missing assets should still be a 404, rather than a page the browser can't use in their place
+        if !self.matches(&request.uri) || has_extension(&request.uri) {
+            return result;
+        }
+
+        let path = self.within_root(&self.root.join(fallback)).await?;
+        let metadata = tokio::fs::metadata(&path).await.map_err(file_error)?;
+        if !metadata.is_file() {
+            return Err(StatusCode::NOT_FOUND.into());
+        }
+
+        Ok(self.serve_file(request, path, metadata).await)
+    }
+
+    /// # Serve Path
+    ///
+    /// Build the response for the file (or directory) at the request's path
+    async fn serve_path(&self, request: &Request) -> Result<Response, HttpError> {
+        let segments = self.relative_segments(&request.uri).ok_or(StatusCode::NOT_FOUND)?;
+        let is_root = segments.is_empty();
+        let path = self.resolve(segments).await?;
+
+        let metadata = tokio::fs::metadata(&path).await.map_err(file_error)?;
+        if metadata.is_dir() {
+            return self.serve_directory(request, path, is_root).await;
+        }
+        if !metadata.is_file() {
+            return Err(StatusCode::NOT_FOUND.into());
+        }
+
+        Ok(self.serve_file(request, path, metadata).await)
+    }
+
+    /// # Serve File
+    ///
+    /// Build the response for a file, sending a precompressed copy of it instead if there is one the client accepts
+    async fn serve_file(&self, request: &Request, path: PathBuf, metadata: std::fs::Metadata) -> Response {
+        // The content type is the type of the original file, whichever copy is sent
+        let content_type = self.mime_types.content_type(&path);
+
+        if self.precompressed {
+            for (encoding, extension) in PRECOMPRESSED.iter().filter(|(encoding, _)| accepts_encoding(request, encoding)) {
+                let mut compressed = path.clone().into_os_string();
+                compressed.push(extension);
+
+                if let Ok(compressed) = self.within_root(Path::new(&compressed)).await {
+                    if let Ok(metadata) = tokio::fs::metadata(&compressed).await {
+                        if metadata.is_file() {
+                            return self.file_response(request, compressed, metadata, content_type, Some(encoding));
+                        }
+                    }
+                }
+            }
+        }
+
+        self.file_response(request, path, metadata, content_type, None)
+    }
+
+    /// # File Response
+    ///
+    /// Build the response for a file - the whole file, the ranges asked for, or `304 Not Modified`. `encoding` is the
+    /// `Content-Encoding` of the file, if it is a precompressed copy.
+    fn file_response(
+        &self,
+        request: &Request,
+        path: PathBuf,
+        metadata: std::fs::Metadata,
+        content_type: String,
+        encoding: Option<&str>,
+    ) -> Response {
+        let file_len = metadata.len();
+        let modified = metadata.modified().ok();
+        let etag = entity_tag(file_len, modified, encoding);
+
+        if is_not_modified(request, &etag, modified) {
+            return self.describe(Response::new(StatusCode::NOT_MODIFIED), &etag, modified);
+        }
+
+        // A range is only sent if the file hasn't changed since the client got the rest of it
+        let ranges = match request.header("Range") {
+            Some(range) if if_range_matches(request.header("If-Range"), &etag, modified) => parse_range(range, file_len),
+            _ => Ranges::Full,
+        };
+
+        let mut response = self.describe(Response::ok().header("Accept-Ranges", "bytes"), &etag, modified);
+        if let Some(encoding) = encoding {
+            response = response.header("Content-Encoding", encoding);
+        }
+        match ranges {
+            Ranges::Full => response.header("Content-Type", content_type).file(path, file_len),
+            Ranges::Unsatisfiable => Response::new(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header("Accept-Ranges", "bytes")
+                .header("Content-Range", format!("bytes */{}", file_len))
+                .text("The requested range is not satisfiable"),
+            Ranges::Partial(ranges) if ranges.len() == 1 => {
+                let range = ranges[0];
+                let mut response = response
+                    .header("Content-Type", content_type)
+                    .header("Content-Range", range.content_range(file_len))
+                    .file_range(path, range.start, range.len());
+                response.set_status(StatusCode::PARTIAL_CONTENT);
+                response
+            }
+            Ranges::Partial(ranges) => {
+                let boundary = boundary();
+                let mut response = response.header("Content-Type", format!("multipart/byteranges; boundary={}", boundary));
+                response.set_status(StatusCode::PARTIAL_CONTENT);
+                response.set_body(multipart_body(&path, &ranges, file_len, &content_type, &boundary));
+                response
+            }
+        }
+    }
+
+    /// # Serve Directory
+    ///
+    /// Build the response for a directory - a redirect to add the trailing slash, its index file, or a listing
+    async fn serve_directory(&self, request: &Request, path: PathBuf, is_root: bool) -> Result<Response, HttpError> {
+        // Relative links in the index page would resolve against the parent directory without the slash
+        if !request.uri.ends_with('/') {
+            return Ok(Response::new(StatusCode::MOVED_PERMANENTLY).header("Location", directory_location(request)));
+        }
+
+        // The index file could be a symlink, so it has to be checked against the root like any other file
+        if let Ok(index) = self.within_root(&path.join(INDEX_FILE)).await {
+            if let Ok(metadata) = tokio::fs::metadata(&index).await {
+                if metadata.is_file() {
+                    return Ok(self.serve_file(request, index, metadata).await);
+                }
+            }
+        }
+
+        if !self.directory_listing {
+            return Err(StatusCode::NOT_FOUND.into());
+        }
+
+        let root = tokio::fs::canonicalize(&self.root).await.map_err(file_error)?;
+        let entries = listing::read_entries(&path, &root, self.hidden_files).await.map_err(file_error)?;
+        let display_path = percent_decode(&request.uri);
+        let wants_json = request.accept().iter().any(|media_type| media_type.starts_with("application/json"));
+        let response = if wants_json {
+            Response::ok().json(&listing::render_json(&display_path, &entries))
+        } else {
+            Response::ok().html(listing::render_html(&display_path, &entries, !is_root))
+        };
+
+        Ok(response.header("Vary", "Accept"))
+    }
+
+    /// # Describe
+    ///
+    /// Add the headers which describe the version of a file being sent - its `ETag`, `Last-Modified` and `Cache-Control`,
+    /// and `Vary` if a precompressed copy could have been sent instead
+    fn describe(&self, response: Response, etag: &str, modified: Option<SystemTime>) -> Response {
+        let mut response = response.header("ETag", etag);
+        if self.precompressed {
+            response = response.header("Vary", "Accept-Encoding");
+        }
+        if let Some(modified) = modified {
+            response = response.header("Last-Modified", format_http_date(modified));
+        }
+        if let Some(cache_control) = self.cache_control.as_ref() {
+            response = response.header("Cache-Control", cache_control.as_str());
+        }
+
+        response
+    }
+
+    /// # Relative Segments
+    ///
+    /// The segments of a request path which come after the prefix, or `None` if the path isn't under it
+    fn relative_segments<'a>(&self, path: &'a str) -> Option<Vec<&'a str>> {
+        let mut segments = split_path(path);
+        for expected in self.prefix.iter() {
+            if segments.next()? != expected {
+                return None;
+            }
+        }
+
+        Some(segments.collect())
+    }
+
+    /// # Resolve
+    ///
+    /// Turn the segments of a request path into the canonical path of a file inside the root directory.
+    ///
+    /// Each segment must be a plain file or directory name once decoded - `..`, separators and the like are refused,
+    /// and hidden names aren't found (unless they are turned on). The path is then canonicalised (following any symlinks),
+    /// and refused if it ends up outside of the root.
+    async fn resolve(&self, segments: Vec<&str>) -> Result<PathBuf, HttpError> {
+        let mut path = PathBuf::new();
+        for segment in segments {
+            let segment = percent_decode(segment);
+            if segment.contains('\0') {
+                return Err(forbidden());
+            }
+
+            let mut components = Path::new(&segment).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) if !self.hidden_files && segment.starts_with('.') => {
+                    return Err(not_found());
+                }
+                (Some(Component::Normal(name)), None) => path.push(name),
+                (Some(Component::CurDir), None) => {}
+                _ => return Err(forbidden()),
+            }
+        }
+
+        self.within_root(&self.root.join(path)).await
+    }
+
+    /// # Within Root
+    ///
+    /// Canonicalise a path (following any symlinks), and check it is still inside the root directory - and, unless
+    /// hidden files are turned on, that a symlink hasn't led it to one
+    async fn within_root(&self, path: &Path) -> Result<PathBuf, HttpError> {
+        let root = tokio::fs::canonicalize(&self.root).await.map_err(file_error)?;
+        let path = tokio::fs::canonicalize(path).await.map_err(file_error)?;
+        let relative = path.strip_prefix(&root).map_err(|_| forbidden())?;
+        if !self.hidden_files && listing::is_hidden(relative) {
+            return Err(not_found());
+        }
+
+        Ok(path)
+    }
+}
+
+/// Where to redirect a request for a directory without a trailing slash - the same path with one, keeping the query.
+/// The path is rebuilt from its segments, so that a path starting with `//` can't become a link to another site.
+fn directory_location(request: &Request) -> String {
+    let segments: Vec<&str> = split_path(&request.uri).collect();
+    let location = format!("/{}/", segments.join("/")).replace("//", "/");
+
+    // The request's `uri` has had the query taken off, so find it in the request line
+    let target = request.raw_request.first().and_then(|line| line.split(' ').nth(1)).unwrap_or("");
+    match target.split_once('?') {
+        Some((_, query)) => format!("{}?{}", location, query),
+        None => location,
+    }
+}
+
+/// Check whether the last segment of a request path has an extension (eg, `/static/app.js`)
+fn has_extension(path: &str) -> bool {
+    split_path(path)
+        .last()
+        .map(|segment| Path::new(&percent_decode(segment)).extension().is_some())
+        .unwrap_or(false)
+}
+
+/// The entity tag of a file, made from its modification time and length (like most web servers do), so that
+/// it changes whenever the file does. Precompressed copies have their encoding added, so they never share a tag
+/// with the original.
+fn entity_tag(file_len: u64, modified: Option<SystemTime>, encoding: Option<&str>) -> String {
+    let modified = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    match encoding {
+        Some(encoding) => format!("\"{:x}-{:x}-{}\"", modified.as_nanos(), file_len, encoding),
+        None => format!("\"{:x}-{:x}\"", modified.as_nanos(), file_len),
+    }
+}
+
+/// Check whether the client's `Accept-Encoding` allows `encoding`. Encodings with a quality of 0 are refused,
+/// and `*` stands in for any encoding which isn't listed.
+fn accepts_encoding(request: &Request, encoding: &str) -> bool {
+    let mut wildcard = false;
+    for item in request.headers.get_all("Accept-Encoding").iter().flat_map(|value| value.split(',')) {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .next()
+            .and_then(|quality| quality.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if name.eq_ignore_ascii_case(encoding) {
+            return quality > 0.0;
+        }
+        if name == "*" {
+            wildcard = quality > 0.0;
+        }
+    }
+
+    wildcard
+}
+
+/// Check whether the client's cached copy of the file is up to date, so `304 Not Modified` can be sent.
+///
+/// `If-None-Match` is used if it was sent, as entity tags are more precise than dates. Otherwise,
+/// `If-Modified-Since` is compared to the modification time of the file.
+fn is_not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    let if_none_match = request.headers.get_all("If-None-Match");
+    if !if_none_match.is_empty() {
+        // Weak comparison - `W/"abc"` matches `"abc"`
+        return if_none_match
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    }
+
+    match (request.header("If-Modified-Since").and_then(parse_http_date), modified) {
+        (Some(since), Some(modified)) => truncate_to_seconds(modified) <= since,
+        _ => false,
+    }
+}
+
+/// Check whether the `If-Range` header (if there is one) allows a range of the file to be sent.
+///
+/// It holds either the entity tag of the client's copy of the file (which must match exactly), or the date it
+/// was last modified.
+fn if_range_matches(if_range: Option<&str>, etag: &str, modified: Option<SystemTime>) -> bool {
+    let if_range = match if_range {
+        Some(if_range) => if_range.trim(),
+        None => return true,
+    };
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return if_range == etag;
+    }
+
+    match (parse_http_date(if_range), modified) {
+        (Some(date), Some(modified)) => date == truncate_to_seconds(modified),
+        _ => false,
+    }
+}
+
+/// Make a boundary for a `multipart/byteranges` response, which is unlikely to appear in the file
+fn boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+
+    format!("{:x}{:08x}", nanos, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Build the body of a `multipart/byteranges` response - each range of the file, with its own headers,
+/// between boundaries
+fn multipart_body(path: &Path, ranges: &[ByteRange], file_len: u64, content_type: &str, boundary: &str) -> Body {
+    let mut parts = Vec::new();
+    for (index, range) in ranges.iter().enumerate() {
+        let separator = if index == 0 { "" } else { "\r\n" };
+        parts.push(Body::Text(format!(
+            "{}--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            separator,
+            boundary,
+            content_type,
+            range.content_range(file_len)
+        )));
+        parts.push(Body::File {
+            path: path.to_path_buf(),
+            offset: range.start,
+            len: range.len(),
+        });
+    }
+    parts.push(Body::Text(format!("\r\n--{}--\r\n", boundary)));
+
+    Body::Parts(parts)
+}
+
+/// The error sent when a request tries to reach a file outside of the root directory
+fn forbidden() -> HttpError {
+    HttpError::new(StatusCode::FORBIDDEN, "The path is not allowed")
+}
+
+/// The error for a file which doesn't exist (or is hidden)
+fn not_found() -> HttpError {
+    HttpError::new(StatusCode::NOT_FOUND, "The file could not be found")
+}
+
+/// Turn an error from opening a file into an error response. The OS error isn't passed on, as it can
+/// give away details about the server.
+fn file_error(e: std::io::Error) -> HttpError {
+    match e.kind() {
+        std::io::ErrorKind::PermissionDenied => forbidden(),
+        _ => not_found(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A directory of files to serve, next to a file outside of it, removed when dropped
+    struct TestDir {
+        path: PathBuf,
+    }
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("micro_http_async_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(path.join("root/css")).unwrap();
+            fs::create_dir_all(path.join("root/.git")).unwrap();
+            fs::write(path.join("root/index.html"), "index").unwrap();
+            fs::write(path.join("root/css/site.css"), "css").unwrap();
+            fs::write(path.join("root/.env"), "secret").unwrap();
+            fs::write(path.join("root/.git/config"), "secret").unwrap();
+            fs::write(path.join("outside.txt"), "secret").unwrap();
+            Self { path }
+        }
+
+        fn root(&self) -> PathBuf {
+            self.path.join("root")
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    async fn resolve(files: &StaticFiles, path: &str) -> Result<PathBuf, StatusCode> {
+        let segments = files.relative_segments(path).unwrap();
+        files.resolve(segments).await.map_err(|error| error.status)
+    }
+
+    #[tokio::test]
+    async fn resolves_files_inside_the_root() {
+        let dir = TestDir::new("inside");
+        let files = StaticFiles::new("/static", dir.root());
+        let root = fs::canonicalize(dir.root()).unwrap();
+
+        assert_eq!(resolve(&files, "/static/css/site.css").await, Ok(root.join("css/site.css")));
+        assert_eq!(resolve(&files, "/static/./index.html").await, Ok(root.join("index.html")));
+        assert_eq!(resolve(&files, "/static/missing.html").await, Err(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn refuses_traversal() {
+        let dir = TestDir::new("traversal");
+        let files = StaticFiles::new("/static", dir.root());
+
+        for path in ["/static/../outside.txt", "/static/%2e%2e/outside.txt", "/static/css/%2E%2E/%2e%2e/outside.txt", "/static/..%2foutside.txt"] {
+            assert_eq!(resolve(&files, path).await, Err(StatusCode::FORBIDDEN), "{}", path);
+        }
+        assert_eq!(resolve(&files, "/static/index.html%00").await, Err(StatusCode::FORBIDDEN));
+    }
+
+    #[tokio::test]
+    async fn hides_hidden_files() {
+        let dir = TestDir::new("hidden");
+        let files = StaticFiles::new("/static", dir.root());
+
+        for path in ["/static/.env", "/static/.git/config", "/static/%2egit/config", "/static/.git"] {
+            assert_eq!(resolve(&files, path).await, Err(StatusCode::NOT_FOUND), "{}", path);
+        }
+
+        let files = files.hidden_files(true);
+        let root = fs::canonicalize(dir.root()).unwrap();
+        assert_eq!(resolve(&files, "/static/.git/config").await, Ok(root.join(".git/config")));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn refuses_symlinks_out_of_the_root_or_to_hidden_files() {
+        let dir = TestDir::new("symlinks");
+        std::os::unix::fs::symlink(dir.path.join("outside.txt"), dir.root().join("outside.txt")).unwrap();
+        std::os::unix::fs::symlink(dir.root().join(".git"), dir.root().join("repo")).unwrap();
+        std::os::unix::fs::symlink(dir.root().join("css"), dir.root().join("styles")).unwrap();
+        let files = StaticFiles::new("/static", dir.root());
+
+        assert_eq!(resolve(&files, "/static/outside.txt").await, Err(StatusCode::FORBIDDEN));
+        assert_eq!(resolve(&files, "/static/repo/config").await, Err(StatusCode::NOT_FOUND));
+
+        // Symlinks which stay inside the root, to files which aren't hidden, are followed
+        let root = fs::canonicalize(dir.root()).unwrap();
+        assert_eq!(resolve(&files, "/static/styles/site.css").await, Ok(root.join("css/site.css")));
+    }
+}